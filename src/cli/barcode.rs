@@ -1,54 +1,792 @@
 use crate::{
-    cli::misc::{reverse_complement, write_record},
+    cli::misc::{check_synced, reverse_complement, write_record},
     errors::FqkitError,
-    utils::{file_reader, file_writer_append},
+    utils::{file_reader, file_writer, file_writer_append},
 };
 use log::{error, info};
-use paraseq::fastq;
+use paraseq::{
+    fastq,
+    fastx::Record,
+    parallel::{PairedParallelProcessor, PairedParallelReader, ProcessError},
+};
+use parking_lot::Mutex;
+use serde_json::json;
 use std::{
     collections::HashMap,
-    io::BufRead,
+    io::{self, BufRead, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-fn barcode_list(file: &String, rev_comp: bool) -> Result<HashMap<Vec<u8>, String>, FqkitError> {
-    let mut maps = HashMap::new();
+// column names allowed in the header line of a plain sample sheet
+const HEADER_COLUMNS: [&str; 10] = [
+    "barcode",
+    "index",
+    "index1",
+    "index2",
+    "i7",
+    "i5",
+    "sample",
+    "sample_id",
+    "sample_name",
+    "name",
+];
+
+// sample sheet rows with line number, blank lines, comment lines (#) and header line are skipped.
+// plain sheets are tab, space or comma separated, only the [Data] section of an Illumina
+// SampleSheet.csv is read, columns given by illumina are picked from it in order
+fn sheet_rows(file: &String, illumina: &[&str]) -> Result<Vec<(usize, Vec<String>)>, FqkitError> {
     let fp = file_reader(Some(file))?;
+    let mut rows = vec![];
+    let mut section: Option<String> = None;
+    let mut columns: Option<Vec<usize>> = None;
+    let mut first = true;
 
-    for line in fp.lines().map_while(Result::ok) {
-        let item = line.split('\t').collect::<Vec<&str>>(); // barcode => sample
-        let bar = if rev_comp {
-            reverse_complement(item[0].as_bytes())
+    for (num, line) in fp.lines().enumerate() {
+        let (num, line) = (num + 1, line?);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            section = Some(line.trim_end_matches(',').to_ascii_lowercase());
+            columns = None;
+            continue;
+        }
+        let item = if line.contains(',') && !line.contains('\t') {
+            line.split(',').map(|x| x.trim().to_string()).collect()
         } else {
-            item[0]
-                .chars()
-                .rev()
-                .collect::<String>()
-                .as_bytes()
-                .to_vec()
+            line.split_whitespace()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>()
         };
-        maps.entry(bar).or_insert(item[1].to_string());
+
+        match section.as_deref() {
+            None => {
+                // header line of plain sheet, eg. "barcode sample", other rows are checked later
+                if std::mem::take(&mut first)
+                    && item.iter().all(|x| {
+                        HEADER_COLUMNS
+                            .iter()
+                            .any(|name| x.eq_ignore_ascii_case(name))
+                    })
+                {
+                    continue;
+                }
+                rows.push((num, item));
+            }
+            Some("[data]") | Some("[bclconvert_data]") => {
+                let Some(columns) = &columns else {
+                    let found = illumina
+                        .iter()
+                        .map(|name| {
+                            item.iter()
+                                .position(|x| x.eq_ignore_ascii_case(name))
+                                .ok_or_else(|| {
+                                    FqkitError::InvalidSampleSheet(
+                                        file.to_string(),
+                                        num,
+                                        format!("column {} not found in [Data] section", name),
+                                    )
+                                })
+                        })
+                        .collect::<Result<Vec<usize>, FqkitError>>()?;
+                    columns = Some(found);
+                    continue;
+                };
+                let picked = columns
+                    .iter()
+                    .map(|i| item.get(*i).cloned().unwrap_or_default())
+                    .collect();
+                rows.push((num, picked));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(rows)
+}
+
+fn is_seq(seq: &str) -> bool {
+    !seq.is_empty()
+        && seq
+            .bytes()
+            .all(|x| matches!(x.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T' | b'N'))
+}
+
+// validated barcode sequence of a sample sheet cell
+fn sheet_seq(file: &str, num: usize, seq: &str, rev_comp: bool) -> Result<Vec<u8>, FqkitError> {
+    if !is_seq(seq) {
+        return Err(FqkitError::InvalidSampleSheet(
+            file.to_string(),
+            num,
+            format!(
+                "invalid barcode sequence '{}', only A, C, G, T and N allowed",
+                seq
+            ),
+        ));
     }
-    Ok(maps)
+    let seq = seq.to_ascii_uppercase().into_bytes();
+    Ok(if rev_comp {
+        reverse_complement(&seq)
+    } else {
+        seq
+    })
+}
+
+// sample names are used as output file names and must be unique
+fn sheet_name(
+    file: &str,
+    num: usize,
+    name: &str,
+    seen: &mut HashMap<String, usize>,
+) -> Result<String, FqkitError> {
+    let reason = if name.is_empty() {
+        "empty sample name".to_string()
+    } else if name.contains('/') {
+        format!("sample name '{}' contains '/'", name)
+    } else if ["unmatched", "ambiguous"].contains(&name) {
+        format!("sample name '{}' is reserved for unassigned reads", name)
+    } else if let Some(first) = seen.get(name) {
+        format!(
+            "duplicated sample name '{}', first seen at line {}",
+            name, first
+        )
+    } else {
+        seen.insert(name.to_string(), num);
+        return Ok(name.to_string());
+    };
+    Err(FqkitError::InvalidSampleSheet(
+        file.to_string(),
+        num,
+        reason,
+    ))
+}
+
+// barcode sample sheet: barcode and sample name, barcodes are used as-is or reverse complemented
+fn barcode_list(file: &String, rev_comp: bool) -> Result<Vec<(Vec<u8>, String)>, FqkitError> {
+    let mut samples = vec![];
+    let mut names = HashMap::new();
+    let mut barcodes: HashMap<Vec<u8>, usize> = HashMap::new();
+
+    for (num, item) in sheet_rows(file, &["index", "Sample_ID"])? {
+        if item.len() < 2 {
+            return Err(FqkitError::InvalidSampleSheet(
+                file.to_string(),
+                num,
+                "sample sheet needs two columns: barcode and sample name".to_string(),
+            ));
+        }
+        let bar = sheet_seq(file, num, &item[0], rev_comp)?;
+        let name = sheet_name(file, num, &item[1], &mut names)?;
+        if let Some(first) = barcodes.insert(bar.clone(), num) {
+            return Err(FqkitError::InvalidSampleSheet(
+                file.to_string(),
+                num,
+                format!(
+                    "duplicated barcode {}, first seen at line {}",
+                    item[0], first
+                ),
+            ));
+        }
+        samples.push((bar, name));
+    }
+    Ok(samples)
+}
+
+// inline barcode position: read 1 or 2, mode 1 (left) or 2 (right), offset from the read end
+// and spacer (linker) bases between barcode and insert
+#[derive(Clone, Copy, Debug)]
+pub struct InlineLayout {
+    pub read: usize,
+    pub mode: usize,
+    pub offset: usize,
+    pub spacer: usize,
+}
+
+impl InlineLayout {
+    // barcode range and kept insert range of a read, None if the read is too short
+    fn split(&self, read_len: usize, bar_len: usize) -> Option<(Range<usize>, Range<usize>)> {
+        let used = self.offset + bar_len + self.spacer;
+        if read_len < used {
+            return None;
+        }
+        if self.mode == 1 {
+            Some((self.offset..self.offset + bar_len, used..read_len))
+        } else {
+            let bar_end = read_len - self.offset;
+            Some((bar_end - bar_len..bar_end, 0..read_len - used))
+        }
+    }
+}
+
+// where to read dual indices, i7 and i5 mismatch limits
+#[derive(Clone, Debug)]
+pub struct DualIndex {
+    pub index1: Option<String>,
+    pub index2: Option<String>,
+    pub header: bool,
+    pub mismatch_i5: usize,
+}
+
+// i7, i5 and sample name
+type DualSample = (Vec<u8>, Vec<u8>, String);
+
+// dual index sample sheet: i7, i5 and sample name, only i5 is reverse complemented if required
+fn dual_barcode_list(file: &String, rev_comp: bool) -> Result<Vec<DualSample>, FqkitError> {
+    let mut samples = vec![];
+    let mut names = HashMap::new();
+    let mut indices: HashMap<(Vec<u8>, Vec<u8>), usize> = HashMap::new();
+
+    for (num, item) in sheet_rows(file, &["index", "index2", "Sample_ID"])? {
+        if item.len() < 3 {
+            return Err(FqkitError::InvalidSampleSheet(
+                file.to_string(),
+                num,
+                "dual index sample sheet needs three columns: i7, i5 and sample name".to_string(),
+            ));
+        }
+        let i7 = sheet_seq(file, num, &item[0], false)?;
+        let i5 = sheet_seq(file, num, &item[1], rev_comp)?;
+        let name = sheet_name(file, num, &item[2], &mut names)?;
+        if let Some(first) = indices.insert((i7.clone(), i5.clone()), num) {
+            return Err(FqkitError::InvalidSampleSheet(
+                file.to_string(),
+                num,
+                format!(
+                    "duplicated index pair {}+{}, first seen at line {}",
+                    item[0], item[1], first
+                ),
+            ));
+        }
+        samples.push((i7, i5, name));
+    }
+    Ok(samples)
+}
+
+// index sequences in header comment, eg. "1:N:0:ACGTACGT+TTGCAAGC", None without both i7 and i5
+fn header_index(id: &[u8]) -> Option<(&[u8], &[u8])> {
+    let comment = id.split(|x| x.is_ascii_whitespace()).nth(1)?;
+    let index = comment.rsplit(|x| *x == b':').next()?;
+    let mut parts = index.splitn(2, |x| *x == b'+');
+    let (i7, i5) = (parts.next()?, parts.next()?);
+    (!i7.is_empty() && !i5.is_empty()).then_some((i7, i5))
+}
+
+// output file name of a sample in outdir
+fn out_path(outdir: &str, name: &str, suffix: &str, gzip: bool, bzip2: bool, xz: bool) -> PathBuf {
+    let ext = if gzip {
+        ".gz"
+    } else if bzip2 {
+        ".bz2"
+    } else if xz {
+        ".xz"
+    } else {
+        ""
+    };
+    PathBuf::from(outdir).join(format!("{}_{}.fq{}", name, suffix, ext))
+}
+
+// unassigned read pairs, no barcode matched or more than one barcode matched equally well
+const UNMATCHED: usize = 0;
+const AMBIGUOUS: usize = 1;
+// bases used to enumerate sequences within mismatch distance of a barcode
+const BASES: [u8; 5] = [b'A', b'C', b'G', b'T', b'N'];
+
+// where and how to write the demultiplex report
+#[derive(Clone, Debug)]
+pub struct DemuxReport {
+    pub tsv: Option<String>,
+    pub json: Option<String>,
+    pub top: usize,
+}
+
+// per sample read counts by mismatches, unassigned read pairs and counts of unmatched barcode sequences
+#[derive(Clone, Default)]
+struct DemuxStats {
+    counts: Vec<Vec<u64>>,
+    unassigned: [u64; 2],
+    unknown: HashMap<Vec<u8>, u64>,
+}
+
+impl DemuxStats {
+    fn new(num: usize, max_mismatch: usize) -> Self {
+        DemuxStats {
+            counts: vec![vec![0; max_mismatch + 1]; num],
+            unassigned: [0; 2],
+            unknown: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, idx: usize, mismatch: usize) {
+        self.counts[idx][mismatch] += 1;
+    }
+
+    fn add_unknown(&mut self, bar: &[u8]) {
+        if !bar.is_empty() {
+            *self.unknown.entry(bar.to_vec()).or_insert(0) += 1;
+        }
+    }
+
+    // add counts of other and reset it
+    fn merge(&mut self, other: &mut DemuxStats) {
+        for (total, count) in self.counts.iter_mut().zip(other.counts.iter_mut()) {
+            for (t, c) in total.iter_mut().zip(count.iter_mut()) {
+                *t += *c;
+                *c = 0;
+            }
+        }
+        for (t, c) in self.unassigned.iter_mut().zip(other.unassigned.iter_mut()) {
+            *t += *c;
+            *c = 0;
+        }
+        for (bar, num) in other.unknown.drain() {
+            *self.unknown.entry(bar).or_insert(0) += num;
+        }
+    }
+
+    fn assigned(&self) -> u64 {
+        self.counts.iter().flatten().sum::<u64>()
+    }
+
+    fn total(&self) -> u64 {
+        self.assigned() + self.unassigned.iter().sum::<u64>()
+    }
+
+    fn write_report(
+        &self,
+        report: &DemuxReport,
+        samples: &[(String, String)],
+        compression_level: u32,
+    ) -> Result<(), FqkitError> {
+        let total = self.total();
+        let percent = |x: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                x as f64 / total as f64 * 100.0
+            }
+        };
+        let mut unknown = self.unknown.iter().collect::<Vec<(&Vec<u8>, &u64)>>();
+        unknown.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        unknown.truncate(report.top);
+
+        if let Some(tsv) = &report.tsv {
+            info!("write demultiplex report to file: {}", tsv);
+            let mut fo = file_writer(Some(tsv), compression_level, 'u')?;
+            let mismatch_head = (0..self.counts.first().map_or(1, |x| x.len()))
+                .map(|x| format!("\tmismatch{}", x))
+                .collect::<String>();
+            writeln!(fo, "sample\tbarcode\treads\tpercent{}", mismatch_head)?;
+            for ((name, bar), count) in samples.iter().zip(self.counts.iter()) {
+                let reads = count.iter().sum::<u64>();
+                let by_mismatch = count.iter().map(|x| format!("\t{}", x)).collect::<String>();
+                writeln!(
+                    fo,
+                    "{}\t{}\t{}\t{:.2}{}",
+                    name,
+                    bar,
+                    reads,
+                    percent(reads),
+                    by_mismatch
+                )?;
+            }
+            for (name, kind) in [("unmatched", UNMATCHED), ("ambiguous", AMBIGUOUS)] {
+                let reads = self.unassigned[kind];
+                writeln!(fo, "{}\t-\t{}\t{:.2}", name, reads, percent(reads))?;
+            }
+            writeln!(fo, "\nunmatched_barcode\treads\tpercent")?;
+            for (bar, reads) in unknown.iter() {
+                writeln!(
+                    fo,
+                    "{}\t{}\t{:.2}",
+                    String::from_utf8_lossy(bar),
+                    reads,
+                    percent(**reads)
+                )?;
+            }
+            fo.flush()?;
+        }
+
+        if let Some(json) = &report.json {
+            info!("write demultiplex report to file: {}", json);
+            let report = json!({
+                "total": total,
+                "assigned": self.assigned(),
+                "unmatched": self.unassigned[UNMATCHED],
+                "ambiguous": self.unassigned[AMBIGUOUS],
+                "samples": samples
+                    .iter()
+                    .zip(self.counts.iter())
+                    .map(|((name, bar), count)| {
+                        let reads = count.iter().sum::<u64>();
+                        json!({
+                            "sample": name,
+                            "barcode": bar,
+                            "reads": reads,
+                            "percent": percent(reads),
+                            "mismatches": count,
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+                "top_unmatched": unknown
+                    .iter()
+                    .map(|(bar, reads)| {
+                        json!({
+                            "barcode": String::from_utf8_lossy(bar),
+                            "reads": reads,
+                            "percent": percent(**reads),
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            });
+            let mut fo = file_writer(Some(json), compression_level, 'u')?;
+            serde_json::to_writer_pretty(&mut fo, &report).map_err(std::io::Error::from)?;
+            fo.write_all(b"\n")?;
+            fo.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// all barcodes within mismatch distance of every possible observed sequence, for O(1) lookup
+#[derive(Clone, Debug, Default)]
+struct MismatchTable {
+    // barcode lengths, longest first
+    lengths: Vec<usize>,
+    // observed sequence => (barcode index, mismatches)
+    map: HashMap<Vec<u8>, Vec<(usize, usize)>>,
+}
+
+impl MismatchTable {
+    fn new<'a>(barcodes: impl Iterator<Item = &'a [u8]>, mismatch: usize) -> Self {
+        let mut table = MismatchTable::default();
+        for (idx, bar) in barcodes.enumerate() {
+            if !table.lengths.contains(&bar.len()) {
+                table.lengths.push(bar.len());
+            }
+            let mut seq = bar.to_vec();
+            expand(&mut seq, 0, mismatch, 0, &mut |seq, dis| {
+                table.map.entry(seq.to_vec()).or_default().push((idx, dis));
+            });
+        }
+        table.lengths.sort_unstable_by(|a, b| b.cmp(a));
+        table
+    }
+
+    fn get(&self, seq: &[u8]) -> &[(usize, usize)] {
+        self.map.get(seq).map_or(&[], |x| x.as_slice())
+    }
+
+    // barcodes matching the start of an index read, all barcode lengths are tried
+    fn get_prefix(&self, seq: &[u8]) -> Vec<(usize, usize)> {
+        self.lengths
+            .iter()
+            .filter(|len| seq.len() >= **len)
+            .flat_map(|len| self.get(&seq[..*len]).iter().copied())
+            .collect()
+    }
+}
+
+// visit every sequence within budget substitutions of seq exactly once, with its distance
+pub fn expand(
+    seq: &mut Vec<u8>,
+    start: usize,
+    budget: usize,
+    dis: usize,
+    visit: &mut impl FnMut(&[u8], usize),
+) {
+    visit(seq, dis);
+    if budget == 0 {
+        return;
+    }
+    for pos in start..seq.len() {
+        let orig = seq[pos];
+        for base in BASES.iter().filter(|x| **x != orig) {
+            seq[pos] = *base;
+            expand(seq, pos + 1, budget - 1, dis + 1, visit);
+        }
+        seq[pos] = orig;
+    }
+}
+
+// best barcode by its key (smaller is better), Err(true) if tied, Err(false) if none matched
+fn assign<K: Ord>(keys: impl Iterator<Item = Option<K>>) -> Result<usize, bool> {
+    let mut best: Option<(usize, K)> = None;
+    let mut tie = false;
+    for (idx, key) in keys.enumerate() {
+        let Some(key) = key else {
+            continue;
+        };
+        match &best {
+            Some((_, b)) if key > *b => {}
+            Some((_, b)) if key == *b => tie = true,
+            _ => {
+                best = Some((idx, key));
+                tie = false;
+            }
+        }
+    }
+    match best {
+        Some(_) if tie => Err(true),
+        Some((idx, _)) => Ok(idx),
+        None => Err(false),
+    }
+}
+
+// two barcodes of the same length within 2*mismatch distance may be both matched by a read
+fn collision(a: &[u8], b: &[u8], mismatch: usize) -> bool {
+    a.len() == b.len() && hamming_dis(a, b) <= 2 * mismatch
 }
 
 #[inline]
-fn hamming_dis(bar: &[u8], seq: &[u8]) -> usize {
+pub fn hamming_dis(bar: &[u8], seq: &[u8]) -> usize {
     bar.iter().zip(seq.iter()).filter(|(a, b)| a != b).count()
 }
 
+#[derive(Clone)]
+enum Matcher {
+    Inline {
+        layout: InlineLayout,
+        table: Arc<MismatchTable>,
+    },
+    Dual {
+        i7: Arc<MismatchTable>,
+        i5: Arc<MismatchTable>,
+    },
+}
+
+// output slots: samples first, then unmatched and ambiguous read pairs,
+// each slot has read1, read2 and barcode (inline samples only) files
+type SlotWriter = Mutex<Vec<Box<dyn Write + Send>>>;
+
+#[derive(Clone)]
+struct Demux {
+    matcher: Matcher,
+    buffers: Vec<Vec<Vec<u8>>>,
+    writers: Arc<Vec<SlotWriter>>,
+    stats: DemuxStats,
+    total_stats: Arc<Mutex<DemuxStats>>,
+}
+
+impl Demux {
+    fn new(matcher: Matcher, writers: Vec<Vec<Box<dyn Write + Send>>>, stats: DemuxStats) -> Self {
+        Demux {
+            matcher,
+            buffers: writers.iter().map(|x| vec![vec![]; x.len()]).collect(),
+            writers: Arc::new(writers.into_iter().map(Mutex::new).collect()),
+            total_stats: Arc::new(Mutex::new(stats.clone())),
+            stats,
+        }
+    }
+
+    fn unassigned<Rf: Record>(&mut self, kind: usize, rec1: &Rf, rec2: &Rf) -> io::Result<()> {
+        self.stats.unassigned[kind] += 1;
+        let slot = &mut self.buffers[self.stats.counts.len() + kind];
+        write_record(&mut slot[0], rec1.id(), rec1.seq(), rec1.qual().unwrap())?;
+        write_record(&mut slot[1], rec2.id(), rec2.seq(), rec2.qual().unwrap())?;
+        Ok(())
+    }
+
+    fn demux_inline<Rf: Record>(&mut self, rec1: &Rf, rec2: &Rf) -> io::Result<()> {
+        let Matcher::Inline { layout, table } = &self.matcher else {
+            return Ok(());
+        };
+        let (bar_rec, other) = if layout.read == 1 {
+            (rec1, rec2)
+        } else {
+            (rec2, rec1)
+        };
+        let seq = bar_rec.seq();
+        // longest barcode wins, then fewest mismatches
+        let mut hit = Err(false);
+        for len in table.lengths.iter() {
+            let Some((bar, keep)) = layout.split(seq.len(), *len) else {
+                continue;
+            };
+            let found = table.get(&seq[bar.clone()]);
+            if !found.is_empty() {
+                hit = assign(found.iter().map(|x| Some(x.1)))
+                    .map(|idx| (found[idx], bar.clone(), keep.clone()));
+                break;
+            }
+        }
+        let ((idx, dis), bar, keep) = match hit {
+            Ok(hit) => hit,
+            Err(tie) => {
+                if !tie {
+                    if let Some(len) = table.lengths.first() {
+                        if let Some((bar, _)) = layout.split(seq.len(), *len) {
+                            self.stats.add_unknown(&seq[bar]);
+                        }
+                    }
+                }
+                let kind = if tie { AMBIGUOUS } else { UNMATCHED };
+                return self.unassigned(kind, rec1, rec2);
+            }
+        };
+
+        self.stats.add(idx, dis);
+        let (bar_mate, other_mate) = if layout.read == 1 { (0, 1) } else { (1, 0) };
+        let qual = bar_rec.qual().unwrap();
+        let slot = &mut self.buffers[idx];
+        write_record(
+            &mut slot[bar_mate],
+            bar_rec.id(),
+            &seq[keep.clone()],
+            &qual[keep],
+        )?;
+        write_record(
+            &mut slot[other_mate],
+            other.id(),
+            other.seq(),
+            other.qual().unwrap(),
+        )?;
+        write_record(&mut slot[2], bar_rec.id(), &seq[bar.clone()], &qual[bar])?;
+        Ok(())
+    }
+
+    fn demux_dual<Rf: Record>(
+        &mut self,
+        rec1: &Rf,
+        rec2: &Rf,
+        i7: &[u8],
+        i5: &[u8],
+    ) -> io::Result<()> {
+        let Matcher::Dual {
+            i7: table7,
+            i5: table5,
+        } = &self.matcher
+        else {
+            return Ok(());
+        };
+        let found5 = table5.get_prefix(i5);
+        // both indices within mismatches, fewest total mismatches wins
+        let found = table7
+            .get_prefix(i7)
+            .into_iter()
+            .filter_map(|(idx, dis7)| {
+                found5
+                    .iter()
+                    .find(|x| x.0 == idx)
+                    .map(|(_, dis5)| (idx, dis7 + dis5))
+            })
+            .collect::<Vec<(usize, usize)>>();
+        match assign(found.iter().map(|x| Some(x.1))) {
+            Ok(i) => {
+                let (idx, dis) = found[i];
+                self.stats.add(idx, dis);
+                let slot = &mut self.buffers[idx];
+                write_record(&mut slot[0], rec1.id(), rec1.seq(), rec1.qual().unwrap())?;
+                write_record(&mut slot[1], rec2.id(), rec2.seq(), rec2.qual().unwrap())?;
+                Ok(())
+            }
+            Err(tie) => {
+                if !tie {
+                    let mut bar = i7.to_vec();
+                    if !i5.is_empty() {
+                        bar.push(b'+');
+                        bar.extend_from_slice(i5);
+                    }
+                    self.stats.add_unknown(&bar);
+                }
+                let kind = if tie { AMBIGUOUS } else { UNMATCHED };
+                self.unassigned(kind, rec1, rec2)
+            }
+        }
+    }
+
+    // write buffered reads and counters of this batch
+    fn flush_batch(&mut self) -> io::Result<()> {
+        self.total_stats.lock().merge(&mut self.stats);
+        for (slot, writer) in self.buffers.iter_mut().zip(self.writers.iter()) {
+            if slot.iter().all(|x| x.is_empty()) {
+                continue;
+            }
+            let mut writer = writer.lock();
+            for (buf, fh) in slot.iter_mut().zip(writer.iter_mut()) {
+                fh.write_all(buf)?;
+                buf.clear();
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        for writer in self.writers.iter() {
+            for fh in writer.lock().iter_mut() {
+                fh.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PairedParallelProcessor for Demux {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        if let Matcher::Inline { .. } = self.matcher {
+            self.demux_inline(&rec1, &rec2)?;
+        } else {
+            let Some((i7, i5)) = header_index(rec1.id()) else {
+                error!(
+                    "no i7+i5 index in read header: {}",
+                    String::from_utf8_lossy(rec1.id())
+                );
+                std::process::exit(1);
+            };
+            let (i7, i5) = (i7.to_vec(), i5.to_vec());
+            self.demux_dual(&rec1, &rec2, &i7, &i5)?;
+        }
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+// output files of samples, unmatched and ambiguous read pairs
+fn slot_writers(
+    names: &[&str],
+    with_barcode: bool,
+    outdir: &str,
+    gzip: bool,
+    bzip2: bool,
+    xz: bool,
+    compression_level: u32,
+) -> Result<Vec<Vec<Box<dyn Write + Send>>>, FqkitError> {
+    let mut writers = vec![];
+    for name in names.iter().chain(["unmatched", "ambiguous"].iter()) {
+        let mut suffix = vec!["1", "2"];
+        if with_barcode && writers.len() < names.len() {
+            suffix.push("barcode");
+        }
+        let mut slot = vec![];
+        for suffix in suffix {
+            let path = out_path(outdir, name, suffix, gzip, bzip2, xz);
+            slot.push(file_writer_append(&path, compression_level)?);
+        }
+        writers.push(slot);
+    }
+    Ok(writers)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn split_fq(
     big_fq1: &String,
     big_fq2: &String,
     bar_file: &String,
     rev_comp: bool,
-    mode: usize,
+    layout: InlineLayout,
     mismatch: usize,
+    index: Option<DualIndex>,
+    report: DemuxReport,
     outdir: &str,
     gzip: bool,
     bzip2: bool,
     xz: bool,
+    ncpu: usize,
     compression_level: u32,
 ) -> Result<(), FqkitError> {
     if !Path::new(outdir).try_exists().unwrap() {
@@ -70,126 +808,388 @@ pub fn split_fq(
         std::process::exit(1);
     }
 
-    if let Ok(maps) = barcode_list(bar_file, rev_comp) {
-        if maps.is_empty() {
-            error!("{}", FqkitError::EmptyFile(bar_file.to_string()));
-            std::process::exit(1);
-        }
-
-        let mut fq_hand = Vec::new();
-        for (bar_seq, name) in maps {
-            let (fq1, fq2, bar) = if gzip {
-                (
-                    PathBuf::from(outdir).join(format!("{}_1.fq.gz", name)),
-                    PathBuf::from(outdir).join(format!("{}_2.fq.gz", name)),
-                    PathBuf::from(outdir).join(format!("{}_barcode.fq.gz", name)),
-                )
-            } else if bzip2 {
-                (
-                    PathBuf::from(outdir).join(format!("{}_1.fq.bz2", name)),
-                    PathBuf::from(outdir).join(format!("{}_2.fq.bz2", name)),
-                    PathBuf::from(outdir).join(format!("{}_barcode.fq.bz2", name)),
-                )
-            } else if xz {
-                (
-                    PathBuf::from(outdir).join(format!("{}_1.fq.xz", name)),
-                    PathBuf::from(outdir).join(format!("{}_2.fq.xz", name)),
-                    PathBuf::from(outdir).join(format!("{}_barcode.fq.xz", name)),
-                )
-            } else {
-                (
-                    PathBuf::from(outdir).join(format!("{}_1.fq", name)),
-                    PathBuf::from(outdir).join(format!("{}_2.fq", name)),
-                    PathBuf::from(outdir).join(format!("{}_barcode.fq", name)),
-                )
-            };
+    if let Some(index) = index {
+        return split_fq_dual(
+            big_fq1,
+            big_fq2,
+            bar_file,
+            rev_comp,
+            index,
+            mismatch,
+            report,
+            outdir,
+            gzip,
+            bzip2,
+            xz,
+            ncpu,
+            compression_level,
+        );
+    }
 
-            let fh1 = file_writer_append(&fq1, compression_level)?;
-            let fh2 = file_writer_append(&fq2, compression_level)?;
-            let fhb = file_writer_append(&bar, compression_level)?;
-            fq_hand.push((bar_seq.clone(), bar_seq.len(), fh1, fh2, fhb));
-        }
-
-        info!("reading from read1 file: {}", big_fq1);
-        let mut fq1_reader = fastq::Reader::new(file_reader(Some(big_fq1))?);
-        info!("reading from read2 file: {}", big_fq2);
-        let mut fq2_reader = fastq::Reader::new(file_reader(Some(big_fq2))?);
-        let mut rset1 = fastq::RecordSet::default();
-        let mut rset2 = fastq::RecordSet::default();
-        let bar_count = fq_hand.len();
-        let (mut read_pair, mut get_pair) = (0u64, 0u64);
-        info!("barcode position mode: {}", mode);
-
-        if mode == 2 {
-            while rset1.fill(&mut fq1_reader)? && rset2.fill(&mut fq2_reader)? {
-                for (rec1, rec2) in rset1
-                    .iter()
-                    .map_while(Result::ok)
-                    .zip(rset2.iter().map_while(Result::ok))
-                {
-                    let read_len = rec2.seq().len();
-                    read_pair += 1;
+    if ![1, 2].contains(&layout.mode) {
+        error!("invalid mode arg, must be 1 or 2 !");
+        std::process::exit(1);
+    }
+    if ![1, 2].contains(&layout.read) {
+        error!("invalid barcode read arg, must be 1 or 2 !");
+        std::process::exit(1);
+    }
 
-                    for idx in 0..bar_count {
-                        if let Some((bar_seq, bar_len, fh1, fh2, fhb)) = fq_hand.get_mut(idx) {
-                            if rec2.seq().len() < *bar_len {
-                                continue;
-                            }
-                            let pos_index = read_len - *bar_len;
-                            let read_part1 = &rec2.seq()[0..pos_index];
-                            let read_part2 = &rec2.seq()[pos_index..];
-                            let qual_part1 = &rec2.qual()[0..pos_index];
-                            let qual_part2 = &rec2.qual()[pos_index..];
-                            if hamming_dis(bar_seq, read_part2) <= mismatch {
-                                get_pair += 1;
-                                write_record(fh1, rec1.id(), rec1.seq(), rec1.qual())?;
-                                write_record(fh2, rec2.id(), read_part1, qual_part1)?;
-                                write_record(fhb, rec2.id(), read_part2, qual_part2)?;
-                                break;
-                            }
-                        }
-                    }
-                }
+    let mut maps = barcode_list(bar_file, rev_comp)?;
+    if maps.is_empty() {
+        error!("{}", FqkitError::EmptyFile(bar_file.to_string()));
+        std::process::exit(1);
+    }
+    maps.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(&b.0)));
+    for (i, (bar1, name1)) in maps.iter().enumerate() {
+        for (bar2, name2) in maps.iter().skip(i + 1) {
+            if collision(bar1, bar2, mismatch) {
+                error!(
+                    "barcodes of sample {} and {} are within {} mismatches, assignment is ambiguous",
+                    name1,
+                    name2,
+                    2 * mismatch
+                );
+                std::process::exit(1);
             }
-        } else if mode == 1 {
-            while rset1.fill(&mut fq1_reader)? && rset2.fill(&mut fq2_reader)? {
-                for (rec1, rec2) in rset1
-                    .iter()
-                    .map_while(Result::ok)
-                    .zip(rset2.iter().map_while(Result::ok))
-                {
-                    read_pair += 1;
-                    for idx in 0..bar_count {
-                        if let Some((bar_seq, bar_len, fh1, fh2, fhb)) = fq_hand.get_mut(idx) {
-                            if rec2.seq().len() < *bar_len {
-                                continue;
-                            }
-                            let pos_index = *bar_len;
-                            let read_part1 = &rec2.seq()[0..pos_index];
-                            let read_part2 = &rec2.seq()[pos_index..];
-                            let qual_part1 = &rec2.qual()[0..pos_index];
-                            let qual_part2 = &rec2.qual()[pos_index..];
-                            if hamming_dis(bar_seq, read_part1) <= mismatch {
-                                get_pair += 1;
-                                write_record(fh1, rec1.id(), rec1.seq(), rec1.qual())?;
-                                write_record(fh2, rec2.id(), read_part2, qual_part2)?;
-                                write_record(fhb, rec2.id(), read_part1, qual_part1)?;
-                                break;
+        }
+    }
+
+    let table = MismatchTable::new(maps.iter().map(|x| x.0.as_slice()), mismatch);
+    info!("barcode lookup table size: {}", table.map.len());
+    let names = maps.iter().map(|x| x.1.as_str()).collect::<Vec<&str>>();
+    let writers = slot_writers(&names, true, outdir, gzip, bzip2, xz, compression_level)?;
+    let demux = Demux::new(
+        Matcher::Inline {
+            layout,
+            table: Arc::new(table),
+        },
+        writers,
+        DemuxStats::new(maps.len(), mismatch),
+    );
+    info!(
+        "barcode in read{}, position mode: {}, offset: {}, spacer: {}",
+        layout.read, layout.mode, layout.offset, layout.spacer
+    );
+
+    info!("reading from read1 file: {}", big_fq1);
+    let fq1_reader = fastq::Reader::new(file_reader(Some(big_fq1))?);
+    info!("reading from read2 file: {}", big_fq2);
+    let fq2_reader = fastq::Reader::new(file_reader(Some(big_fq2))?);
+    fq1_reader.process_parallel_paired(fq2_reader, demux.clone(), ncpu)?;
+    demux.finish()?;
+
+    let samples = maps
+        .iter()
+        .map(|(bar, name)| (name.clone(), String::from_utf8_lossy(bar).to_string()))
+        .collect::<Vec<(String, String)>>();
+    demux_summary(
+        &demux.total_stats.lock(),
+        &report,
+        &samples,
+        compression_level,
+    )
+}
+
+fn demux_summary(
+    stats: &DemuxStats,
+    report: &DemuxReport,
+    samples: &[(String, String)],
+    compression_level: u32,
+) -> Result<(), FqkitError> {
+    info!(
+        "unmatched read pairs: {}, ambiguous read pairs: {}",
+        stats.unassigned[UNMATCHED], stats.unassigned[AMBIGUOUS]
+    );
+    stats.write_report(report, samples, compression_level)?;
+    info!(
+        "data split rate: {:.4}%",
+        stats.assigned() as f64 / stats.total() as f64 * 100.0
+    );
+    Ok(())
+}
+
+// dual index demultiplex in parallel, indices are read from read1 header comment,
+// or from I1/I2 fastq files in step with read1 and read2
+#[allow(clippy::too_many_arguments)]
+fn split_fq_dual(
+    big_fq1: &String,
+    big_fq2: &String,
+    bar_file: &String,
+    rev_comp: bool,
+    index: DualIndex,
+    mismatch: usize,
+    report: DemuxReport,
+    outdir: &str,
+    gzip: bool,
+    bzip2: bool,
+    xz: bool,
+    ncpu: usize,
+    compression_level: u32,
+) -> Result<(), FqkitError> {
+    if index.header == (index.index1.is_some() || index.index2.is_some()) {
+        error!(
+            "indices must be read from either -i/--index1 and -j/--index2 files or read header (-H/--header)"
+        );
+        std::process::exit(1);
+    }
+    if !index.header && (index.index1.is_none() || index.index2.is_none()) {
+        error!(
+            "both -i/--index1 and -j/--index2 files are required, sample sheet has i7 and i5 indices"
+        );
+        std::process::exit(1);
+    }
+    let samples = dual_barcode_list(bar_file, rev_comp)?;
+    if samples.is_empty() {
+        error!("{}", FqkitError::EmptyFile(bar_file.to_string()));
+        std::process::exit(1);
+    }
+    info!(
+        "dual index mode, i7 mismatch: {}, i5 mismatch: {}",
+        mismatch, index.mismatch_i5
+    );
+    for (i, (i7, i5, name1)) in samples.iter().enumerate() {
+        for (j7, j5, name2) in samples.iter().skip(i + 1) {
+            if collision(i7, j7, mismatch) && collision(i5, j5, index.mismatch_i5) {
+                error!(
+                    "indices of sample {} and {} are within {} (i7) and {} (i5) mismatches, assignment is ambiguous",
+                    name1,
+                    name2,
+                    2 * mismatch,
+                    2 * index.mismatch_i5
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let names = samples.iter().map(|x| x.2.as_str()).collect::<Vec<&str>>();
+    let writers = slot_writers(&names, false, outdir, gzip, bzip2, xz, compression_level)?;
+    let demux = Demux::new(
+        Matcher::Dual {
+            i7: Arc::new(MismatchTable::new(
+                samples.iter().map(|x| x.0.as_slice()),
+                mismatch,
+            )),
+            i5: Arc::new(MismatchTable::new(
+                samples.iter().map(|x| x.1.as_slice()),
+                index.mismatch_i5,
+            )),
+        },
+        writers,
+        DemuxStats::new(samples.len(), mismatch + index.mismatch_i5),
+    );
+
+    info!("reading from read1 file: {}", big_fq1);
+    let fq1_reader = fastq::Reader::new(file_reader(Some(big_fq1))?);
+    info!("reading from read2 file: {}", big_fq2);
+    let fq2_reader = fastq::Reader::new(file_reader(Some(big_fq2))?);
+    if index.header {
+        fq1_reader.process_parallel_paired(fq2_reader, demux.clone(), ncpu)?;
+    } else {
+        let mut readers = vec![("read1", fq1_reader), ("read2", fq2_reader)];
+        for (name, file) in [("index1", &index.index1), ("index2", &index.index2)] {
+            if let Some(file) = file {
+                info!("reading from {} file: {}", name, file);
+                readers.push((name, fastq::Reader::new(file_reader(Some(file))?)));
+            }
+        }
+        let mut rsets = readers
+            .iter()
+            .map(|_| fastq::RecordSet::default())
+            .collect::<Vec<fastq::RecordSet>>();
+
+        loop {
+            let mut filled = false;
+            for (rset, (_, reader)) in rsets.iter_mut().zip(readers.iter_mut()) {
+                filled |= rset.fill(reader)?;
+            }
+            if !filled {
+                break;
+            }
+            let records = rsets
+                .iter()
+                .map(|x| x.iter().collect::<Result<Vec<fastq::RefRecord>, _>>())
+                .collect::<Result<Vec<_>, _>>()?;
+            check_synced(
+                &readers.iter().map(|x| x.0).collect::<Vec<&str>>(),
+                &records,
+            );
+
+            // assign read pairs of this batch in parallel, each worker writes its own buffers
+            let pairs = (0..records[0].len())
+                .map(|i| {
+                    (
+                        &records[0][i],
+                        &records[1][i],
+                        records[2][i].seq(),
+                        records[3][i].seq(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let chunk = pairs.len().div_ceil(ncpu.max(1)).max(1);
+            std::thread::scope(|scope| -> io::Result<()> {
+                let handles = pairs
+                    .chunks(chunk)
+                    .map(|part| {
+                        let mut worker = demux.clone();
+                        scope.spawn(move || -> io::Result<()> {
+                            for (rec1, rec2, i7, i5) in part {
+                                worker.demux_dual(*rec1, *rec2, i7, i5)?;
                             }
-                        }
-                    }
+                            worker.flush_batch()
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                for handle in handles {
+                    handle.join().expect("demultiplex worker panicked")?;
                 }
-            }
-        } else {
-            error!("invalid mode arg, must be 1 or 2 !");
-            std::process::exit(1);
+                Ok(())
+            })?;
         }
+    }
+    demux.finish()?;
+
+    let samples = samples
+        .iter()
+        .map(|(i7, i5, name)| {
+            (
+                name.clone(),
+                format!(
+                    "{}+{}",
+                    String::from_utf8_lossy(i7),
+                    String::from_utf8_lossy(i5)
+                ),
+            )
+        })
+        .collect::<Vec<(String, String)>>();
+    demux_summary(
+        &demux.total_stats.lock(),
+        &report,
+        &samples,
+        compression_level,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_in_header() {
+        let id = b"A00:1:FC:1:1101:1000:1000 1:N:0:ACGTACGT+TTGCAAGC";
+        assert_eq!(header_index(id), Some((&b"ACGTACGT"[..], &b"TTGCAAGC"[..])));
+        // single index header, i5 is required by dual index sample sheet
+        assert_eq!(header_index(b"r1 1:N:0:ACGTACGT"), None);
+        assert_eq!(header_index(b"r1 1:N:0:ACGTACGT+"), None);
+        assert_eq!(header_index(b"r1"), None);
+        // index reads are matched to reads by name
+        assert_eq!(crate::cli::misc::read_name(b"r1/1 1:N:0:ACGT"), b"r1");
+        assert_eq!(crate::cli::misc::read_name(b"r1 3:N:0:ACGT"), b"r1");
+    }
+
+    #[test]
+    fn sample_sheet() {
+        // sheets in a directory of this test run only, removed at the end
+        let dir = std::env::temp_dir().join(format!("fqkit_sample_sheet_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sheet = |name: &str, text: &str| {
+            let path = dir.join(name).to_string_lossy().to_string();
+            std::fs::write(&path, text).unwrap();
+            path
+        };
+
+        let plain = sheet(
+            "plain.tsv",
+            "# sheet\nbarcode\tsample\nacgt\ts1\nTTGCA s2\n\n",
+        );
+        assert_eq!(
+            barcode_list(&plain, false).unwrap(),
+            vec![
+                (b"ACGT".to_vec(), "s1".to_string()),
+                (b"TTGCA".to_vec(), "s2".to_string())
+            ]
+        );
+        assert_eq!(barcode_list(&plain, true).unwrap()[0].0, b"ACGT".to_vec());
 
-        info!(
-            "data split rate: {:.4}%",
-            get_pair as f64 / read_pair as f64 * 100.0
+        let illumina = sheet(
+            "SampleSheet.csv",
+            "[Header],,\nIEMFileVersion,5,\n[Data],,\nSample_ID,index,index2\ns1,ACGTACGT,TTGCAAGC\ns2,GGATCCAA,CATGCATG\n",
         );
+        let samples = dual_barcode_list(&illumina, false).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(
+            samples[1],
+            (b"GGATCCAA".to_vec(), b"CATGCATG".to_vec(), "s2".to_string())
+        );
+
+        for (text, reason) in [
+            ("ACGT\n", "line 1: sample sheet needs two columns"),
+            (
+                "ACGT\ts1\nACGX\ts2\n",
+                "line 2: invalid barcode sequence 'ACGX'",
+            ),
+            (
+                "ACGT\ts1\nTTGC\ts1\n",
+                "line 2: duplicated sample name 's1', first seen at line 1",
+            ),
+            (
+                "ACGT\ts1\nACGT\ts2\n",
+                "line 2: duplicated barcode ACGT, first seen at line 1",
+            ),
+            (
+                "# sheet\nACGX\ts1\nACGT\ts2\n",
+                "line 2: invalid barcode sequence 'ACGX'",
+            ),
+            (
+                "barcode\tsample\tlane\nACGT\ts1\n",
+                "line 1: invalid barcode sequence 'barcode'",
+            ),
+        ] {
+            let bad = sheet("bad.tsv", text);
+            let err = barcode_list(&bad, false).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}", err);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    Ok(())
+    #[test]
+    fn mismatch_table() {
+        let table = MismatchTable::new([&b"ACGT"[..], b"TTGCA", b"ACGA"].into_iter(), 1);
+        assert_eq!(table.lengths, vec![5, 4]);
+        // 1 + 4 * 4 sequences within 1 mismatch of each barcode
+        assert_eq!(table.get(b"ACGT"), &[(0, 0), (2, 1)]);
+        assert_eq!(table.get(b"ACGN"), &[(0, 1), (2, 1)]);
+        assert_eq!(table.get(b"TTGGA"), &[(1, 1)]);
+        assert!(table.get(b"AAAA").is_empty());
+        assert_eq!(table.get_prefix(b"TTGCAAC"), vec![(1, 0)]);
+        let mut num = 0;
+        expand(&mut b"ACGTAC".to_vec(), 0, 2, 0, &mut |_, _| num += 1);
+        assert_eq!(num, 1 + 6 * 4 + 15 * 16);
+    }
+
+    #[test]
+    fn assign_and_collision() {
+        assert_eq!(assign([None, Some(2), Some(1)].into_iter()), Ok(2));
+        assert_eq!(assign([Some(1), Some(2), Some(1)].into_iter()), Err(true));
+        assert_eq!(assign([Some(0), Some(1), Some(1)].into_iter()), Ok(0));
+        assert_eq!(assign([None::<usize>, None].into_iter()), Err(false));
+        assert!(collision(b"ACGTACGT", b"ACGTACCA", 1));
+        assert!(!collision(b"ACGTACGT", b"ACGTTCCA", 1));
+        assert!(!collision(b"ACGTACGT", b"ACGTACG", 1));
+    }
+
+    #[test]
+    fn inline_layout() {
+        let mut layout = InlineLayout {
+            read: 1,
+            mode: 1,
+            offset: 2,
+            spacer: 3,
+        };
+        assert_eq!(layout.split(20, 6), Some((2..8, 11..20)));
+        assert_eq!(layout.split(10, 6), None);
+        layout.mode = 2;
+        assert_eq!(layout.split(20, 6), Some((12..18, 0..9)));
+    }
 }
@@ -0,0 +1,268 @@
+use super::{
+    barcode::{expand, hamming_dis},
+    misc::write_record,
+};
+use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
+use log::{error, info};
+use paraseq::{
+    fastq,
+    fastx::Record,
+    parallel::{PairedParallelProcessor, PairedParallelReader, ProcessError},
+};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, Write},
+    sync::Arc,
+};
+
+type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+// cell barcode and umi position in read1, umi follows the cell barcode
+#[derive(Clone, Copy, Debug)]
+pub struct CellLayout {
+    pub offset: usize,
+    pub cb_len: usize,
+    pub umi_len: usize,
+}
+
+// whitelist barcodes, first column of each line, blank and comment lines (#) are skipped
+fn load_whitelist(file: &String, cb_len: usize) -> Result<HashSet<Vec<u8>>, FqkitError> {
+    let mut whitelist = HashSet::new();
+    let fp = file_reader(Some(file))?;
+    for (num, line) in fp.lines().enumerate() {
+        let line = line?;
+        let Some(bar) = line.split_whitespace().next() else {
+            continue;
+        };
+        if bar.starts_with('#') {
+            continue;
+        }
+        if bar.len() != cb_len {
+            error!(
+                "line {} in whitelist {}: barcode {} length is not {}",
+                num + 1,
+                file,
+                bar,
+                cb_len
+            );
+            std::process::exit(1);
+        }
+        whitelist.insert(bar.to_ascii_uppercase().into_bytes());
+    }
+    Ok(whitelist)
+}
+
+// corrected barcode and mismatches, the candidate whose mismatched base has the lowest quality
+// is preferred, None if no candidate or candidates are tied
+fn correct_barcode(
+    whitelist: &HashSet<Vec<u8>>,
+    bar: &[u8],
+    qual: &[u8],
+) -> Option<(Vec<u8>, usize)> {
+    if whitelist.contains(bar) {
+        return Some((bar.to_vec(), 0));
+    }
+    let mut best: Option<(u8, Vec<u8>)> = None;
+    let mut tie = false;
+    expand(&mut bar.to_vec(), 0, 1, 0, &mut |seq, dis| {
+        if dis == 0 || !whitelist.contains(seq) {
+            return;
+        }
+        let pos = bar
+            .iter()
+            .zip(seq.iter())
+            .position(|(a, b)| a != b)
+            .unwrap();
+        match &best {
+            Some((q, _)) if qual[pos] > *q => {}
+            Some((q, _)) if qual[pos] == *q => tie = true,
+            _ => {
+                best = Some((qual[pos], seq.to_vec()));
+                tie = false;
+            }
+        }
+    });
+    best.filter(|_| !tie).map(|(_, seq)| {
+        let dis = hamming_dis(bar, &seq);
+        (seq, dis)
+    })
+}
+
+#[derive(Clone)]
+struct CellCorrector {
+    layout: CellLayout,
+    whitelist: Arc<HashSet<Vec<u8>>>,
+    buffer: Vec<u8>,
+    // reads with exact barcode, corrected barcode, uncorrected barcode (dropped)
+    count: [usize; 3],
+    cells: HashMap<Vec<u8>, usize>,
+    total_count: Arc<Mutex<[usize; 3]>>,
+    total_cells: Arc<Mutex<HashMap<Vec<u8>, usize>>>,
+    writer: Writer,
+}
+
+impl CellCorrector {
+    fn correct<Rf: Record>(&mut self, rec1: &Rf, rec2: &Rf) -> std::io::Result<()> {
+        let cb_end = self.layout.offset + self.layout.cb_len;
+        let umi_end = cb_end + self.layout.umi_len;
+        let (seq, qual) = (rec1.seq(), rec1.qual().unwrap());
+        if seq.len() < umi_end {
+            self.count[2] += 1;
+            return Ok(());
+        }
+        let raw = seq[self.layout.offset..cb_end].to_ascii_uppercase();
+        let Some((bar, dis)) =
+            correct_barcode(&self.whitelist, &raw, &qual[self.layout.offset..cb_end])
+        else {
+            self.count[2] += 1;
+            return Ok(());
+        };
+        self.count[if dis == 0 { 0 } else { 1 }] += 1;
+
+        let mut name = rec2.id().to_vec();
+        name.extend_from_slice(b" CB:Z:");
+        name.extend_from_slice(&bar);
+        if self.layout.umi_len > 0 {
+            name.extend_from_slice(b" RX:Z:");
+            name.extend_from_slice(&seq[cb_end..umi_end]);
+        }
+        write_record(&mut self.buffer, &name, rec2.seq(), rec2.qual().unwrap())?;
+        *self.cells.entry(bar).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> std::io::Result<()> {
+        let mut total_count = self.total_count.lock();
+        for (t, c) in total_count.iter_mut().zip(self.count.iter()) {
+            *t += *c;
+        }
+        drop(total_count);
+        let mut total_cells = self.total_cells.lock();
+        for (bar, num) in self.cells.drain() {
+            *total_cells.entry(bar).or_insert(0) += num;
+        }
+        drop(total_cells);
+
+        let mut writer = self.writer.lock();
+        writer.write_all(&self.buffer)?;
+        writer.flush()?;
+
+        // reset for next batch
+        self.buffer.clear();
+        self.count = [0; 3];
+        Ok(())
+    }
+}
+
+impl PairedParallelProcessor for CellCorrector {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        self.correct(&rec1, &rec2)?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn correct_cell_barcode(
+    read1: &String,
+    read2: &String,
+    whitelist: &String,
+    layout: CellLayout,
+    counts: Option<&String>,
+    ncpu: usize,
+    out: Option<&String>,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), FqkitError> {
+    if layout.cb_len == 0 {
+        error!("cell barcode length must be greater than 0");
+        std::process::exit(1);
+    }
+    let barcodes = load_whitelist(whitelist, layout.cb_len)?;
+    if barcodes.is_empty() {
+        error!("{}", FqkitError::EmptyFile(whitelist.to_string()));
+        std::process::exit(1);
+    }
+    info!("whitelist barcodes: {}", barcodes.len());
+    info!(
+        "cell barcode offset: {}, length: {}, umi length: {}",
+        layout.offset, layout.cb_len, layout.umi_len
+    );
+
+    let writer = file_writer(out, compression_level, stdout_type)?;
+    let corrector = CellCorrector {
+        layout,
+        whitelist: Arc::new(barcodes),
+        buffer: vec![],
+        count: [0; 3],
+        cells: HashMap::new(),
+        total_count: Arc::new(Mutex::new([0; 3])),
+        total_cells: Arc::new(Mutex::new(HashMap::new())),
+        writer: Arc::new(Mutex::new(writer)),
+    };
+    let fq_reader1 = file_reader(Some(read1)).map(fastq::Reader::new)?;
+    let fq_reader2 = file_reader(Some(read2)).map(fastq::Reader::new)?;
+    fq_reader1.process_parallel_paired(fq_reader2, corrector.clone(), ncpu)?;
+
+    let count = corrector.total_count.lock();
+    let total = count.iter().sum::<usize>();
+    info!("total read pairs: {}", total);
+    info!(
+        "exact barcode: {}, corrected barcode: {}, uncorrected barcode (dropped): {}",
+        count[0], count[1], count[2]
+    );
+    let cells = corrector.total_cells.lock();
+    info!("cell barcodes with reads: {}", cells.len());
+    if let Some(counts) = counts {
+        info!("write reads per cell barcode to file: {}", counts);
+        let mut cells = cells.iter().collect::<Vec<(&Vec<u8>, &usize)>>();
+        cells.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let mut fo = file_writer(Some(counts), compression_level, 'u')?;
+        writeln!(fo, "barcode\treads")?;
+        for (bar, num) in cells {
+            writeln!(fo, "{}\t{}", String::from_utf8_lossy(bar), num)?;
+        }
+        fo.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitelist_correction() {
+        let whitelist = [b"AAAA".to_vec(), b"ACCA".to_vec(), b"TTTT".to_vec()]
+            .into_iter()
+            .collect::<HashSet<Vec<u8>>>();
+        assert_eq!(
+            correct_barcode(&whitelist, b"TTTT", b"IIII"),
+            Some((b"TTTT".to_vec(), 0))
+        );
+        assert_eq!(
+            correct_barcode(&whitelist, b"TTGT", b"IIII"),
+            Some((b"TTTT".to_vec(), 1))
+        );
+        assert_eq!(
+            correct_barcode(&whitelist, b"ANAA", b"I#II"),
+            Some((b"AAAA".to_vec(), 1))
+        );
+        // ACAA is one mismatch from both AAAA and ACCA, low quality base is the more likely error
+        assert_eq!(
+            correct_barcode(&whitelist, b"ACAA", b"I#II"),
+            Some((b"AAAA".to_vec(), 1))
+        );
+        assert_eq!(
+            correct_barcode(&whitelist, b"ACAA", b"II#I"),
+            Some((b"ACCA".to_vec(), 1))
+        );
+        assert_eq!(correct_barcode(&whitelist, b"ACAA", b"IIII"), None);
+        assert_eq!(correct_barcode(&whitelist, b"GGGG", b"IIII"), None);
+    }
+}
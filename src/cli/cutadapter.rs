@@ -1,101 +1,1094 @@
-use super::misc::write_record;
+use super::{
+    join::find_overlap,
+    misc::{reverse_complement, write_record},
+};
 use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
-use log::{error, warn};
-use paraseq::{fasta, fastq};
-use std::collections::HashMap;
+use log::{error, info, warn};
+use paraseq::{
+    fasta, fastq,
+    fastx::Record,
+    parallel::{
+        PairedParallelProcessor, PairedParallelReader, ParallelProcessor, ParallelReader,
+        ProcessError,
+    },
+};
+use parking_lot::Mutex;
+use serde_json::json;
+use std::{collections::HashSet, io::Write, sync::Arc};
 
-pub fn cut_adapter(
-    input: Option<&String>,
-    seqfile: &String,
-    left: bool,
-    miss: usize,
-    out: Option<&String>,
-    compression_level: u32,
-    stdout_type: char,
-) -> Result<(), FqkitError> {
+// semi-global aligner as cutadapt, unit cost for mismatch, insertion and deletion
+#[derive(Clone, Copy, Debug)]
+pub struct Aligner {
+    pub max_error_rate: f64,
+    pub min_overlap: usize,
+    pub indels: bool,
+}
+
+// adapter subcommand defaults, also used for adapter trimming in filter
+impl Default for Aligner {
+    fn default() -> Self {
+        Aligner {
+            max_error_rate: 0.1,
+            min_overlap: 3,
+            indels: true,
+        }
+    }
+}
+
+// which ends of adapter and read may be skipped without penalty
+#[derive(Clone, Copy, Debug)]
+pub struct AlignEnds {
+    pub start_in_adapter: bool,
+    pub start_in_read: bool,
+    pub stop_in_adapter: bool,
+    pub stop_in_read: bool,
+}
+
+impl AlignEnds {
+    // 3' adapter, anywhere in the read or a prefix of it hanging off the read end
+    pub const BACK: AlignEnds = AlignEnds {
+        start_in_adapter: false,
+        start_in_read: true,
+        stop_in_adapter: true,
+        stop_in_read: true,
+    };
+    // 5' adapter, anywhere in the read or a suffix of it at the read start
+    pub const FRONT: AlignEnds = AlignEnds {
+        start_in_adapter: true,
+        start_in_read: true,
+        stop_in_adapter: false,
+        stop_in_read: true,
+    };
+    // anchored 3' adapter, the whole adapter ends at the read end
+    pub const ANCHORED_BACK: AlignEnds = AlignEnds {
+        start_in_adapter: false,
+        start_in_read: true,
+        stop_in_adapter: false,
+        stop_in_read: false,
+    };
+    // anchored 5' adapter, the whole adapter starts at read position 0
+    pub const ANCHORED_FRONT: AlignEnds = AlignEnds {
+        start_in_adapter: false,
+        start_in_read: false,
+        stop_in_adapter: false,
+        stop_in_read: true,
+    };
+}
+
+// aligned region, [start, end) on read and adapter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdapterMatch {
+    pub read_start: usize,
+    pub read_end: usize,
+    pub adapter_start: usize,
+    pub adapter_end: usize,
+    pub matches: usize,
+    pub errors: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    cost: usize,
+    matches: usize,
+    // start position of the alignment in adapter and read
+    origin: (usize, usize),
+}
+
+impl Cell {
+    fn better(&self, other: &Cell) -> bool {
+        self.cost < other.cost || (self.cost == other.cost && self.matches > other.matches)
+    }
+}
+
+impl Aligner {
+    // best alignment of adapter in read: most matched bases, then fewest errors
+    pub fn locate(&self, adapter: &[u8], read: &[u8], ends: AlignEnds) -> Option<AdapterMatch> {
+        let (m, n) = (adapter.len(), read.len());
+        if m == 0 || n == 0 {
+            return None;
+        }
+        let gap = if self.indels { 1 } else { usize::MAX / 4 };
+
+        // row i: first i bases of adapter aligned, column j: first j bases of read
+        let mut prev = (0..=n)
+            .map(|j| Cell {
+                cost: if ends.start_in_read {
+                    0
+                } else {
+                    j.saturating_mul(gap)
+                },
+                matches: 0,
+                origin: (0, if ends.start_in_read { j } else { 0 }),
+            })
+            .collect::<Vec<Cell>>();
+        let mut best: Option<AdapterMatch> = None;
+        self.check_row(&prev, 0, m, n, ends, &mut best);
+
+        for i in 1..=m {
+            let mut row = Vec::with_capacity(n + 1);
+            row.push(Cell {
+                cost: if ends.start_in_adapter {
+                    0
+                } else {
+                    i.saturating_mul(gap)
+                },
+                matches: 0,
+                origin: (if ends.start_in_adapter { i } else { 0 }, 0),
+            });
+            for j in 1..=n {
+                let hit = base_match(adapter[i - 1], read[j - 1]);
+                let diag = &prev[j - 1];
+                let mut cell = Cell {
+                    cost: diag.cost.saturating_add(usize::from(!hit)),
+                    matches: diag.matches + usize::from(hit),
+                    origin: diag.origin,
+                };
+                let up = &prev[j];
+                let up = Cell {
+                    cost: up.cost.saturating_add(gap),
+                    ..*up
+                };
+                if up.better(&cell) {
+                    cell = up;
+                }
+                let left = &row[j - 1];
+                let left = Cell {
+                    cost: left.cost.saturating_add(gap),
+                    ..*left
+                };
+                if left.better(&cell) {
+                    cell = left;
+                }
+                row.push(cell);
+            }
+            self.check_row(&row, i, m, n, ends, &mut best);
+            prev = row;
+        }
+        best
+    }
+
+    // collect valid alignment ends in row i
+    fn check_row(
+        &self,
+        row: &[Cell],
+        i: usize,
+        m: usize,
+        n: usize,
+        ends: AlignEnds,
+        best: &mut Option<AdapterMatch>,
+    ) {
+        for (j, cell) in row.iter().enumerate() {
+            let end_ok =
+                (i == m && (j == n || ends.stop_in_read)) || (j == n && ends.stop_in_adapter);
+            if !end_ok {
+                continue;
+            }
+            let (adapter_start, read_start) = cell.origin;
+            let aligned = i.saturating_sub(adapter_start);
+            if aligned == 0 || aligned < self.min_overlap.min(m) {
+                continue;
+            }
+            if cell.cost as f64 > self.max_error_rate * aligned as f64 {
+                continue;
+            }
+            let hit = AdapterMatch {
+                read_start,
+                read_end: j,
+                adapter_start,
+                adapter_end: i,
+                matches: cell.matches,
+                errors: cell.cost,
+            };
+            let replace = match best {
+                None => true,
+                Some(b) => {
+                    hit.matches > b.matches || (hit.matches == b.matches && hit.errors < b.errors)
+                }
+            };
+            if replace {
+                *best = Some(hit);
+            }
+        }
+    }
+}
+
+// IUPAC code as bit set of A, C, G, T, other letters match nothing
+fn iupac_bits(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => 0b0001,
+        b'C' => 0b0010,
+        b'G' => 0b0100,
+        b'T' | b'U' => 0b1000,
+        b'R' => 0b0101,
+        b'Y' => 0b1010,
+        b'S' => 0b0110,
+        b'W' => 0b1001,
+        b'K' => 0b1100,
+        b'M' => 0b0011,
+        b'B' => 0b1110,
+        b'D' => 0b1101,
+        b'H' => 0b1011,
+        b'V' => 0b0111,
+        b'N' => 0b1111,
+        _ => 0,
+    }
+}
+
+// IUPAC wildcards in read or adapter match any base they stand for
+fn base_match(a: u8, b: u8) -> bool {
+    a.eq_ignore_ascii_case(&b) || iupac_bits(a) & iupac_bits(b) != 0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdapterType {
+    Back,
+    Front,
+    // ADAPTER$
+    AnchoredBack,
+    // ^ADAPTER
+    AnchoredFront,
+}
+
+impl AdapterType {
+    pub fn ends(&self) -> AlignEnds {
+        match self {
+            AdapterType::Back => AlignEnds::BACK,
+            AdapterType::Front => AlignEnds::FRONT,
+            AdapterType::AnchoredBack => AlignEnds::ANCHORED_BACK,
+            AdapterType::AnchoredFront => AlignEnds::ANCHORED_FRONT,
+        }
+    }
+
+    pub fn is_front(&self) -> bool {
+        matches!(self, AdapterType::Front | AdapterType::AnchoredFront)
+    }
+
+    pub fn is_anchored(&self) -> bool {
+        matches!(self, AdapterType::AnchoredBack | AdapterType::AnchoredFront)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Adapter {
+    pub name: String,
+    pub seq: Vec<u8>,
+    pub kind: AdapterType,
+    // 3' part of a linked adapter FRONT...BACK, seq and kind hold the 5' part
+    pub linked: Option<(Vec<u8>, AdapterType)>,
+}
+
+// kept range [start, end) of read after removing an adapter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdapterHit {
+    pub start: usize,
+    pub end: usize,
+    pub matches: usize,
+    pub errors: usize,
+}
+
+impl Adapter {
+    // plain 3' adapter
+    pub fn new(name: &str, seq: &[u8]) -> Self {
+        Adapter {
+            name: name.to_string(),
+            seq: seq.to_ascii_uppercase(),
+            kind: AdapterType::Back,
+            linked: None,
+        }
+    }
+
+    // parse adapter notation as cutadapt: ADAPTER (3'), ^ADAPTER (anchored 5'), ADAPTER$ (anchored 3'),
+    // and linked adapter FRONT...BACK, the front part is 5' adapter and the back part is 3' adapter
+    pub fn parse(name: &str, spec: &str) -> Result<Self, String> {
+        let spec = spec.trim().to_ascii_uppercase();
+        if let Some((front, back)) = spec.split_once("...") {
+            let (front_seq, front_kind) = split_anchor(front, true)?;
+            let (back_seq, back_kind) = split_anchor(back, false)?;
+            return Ok(Adapter {
+                name: name.to_string(),
+                seq: front_seq,
+                kind: front_kind,
+                linked: Some((back_seq, back_kind)),
+            });
+        }
+        let (seq, kind) = if spec.starts_with('^') {
+            split_anchor(&spec, true)?
+        } else {
+            split_anchor(&spec, false)?
+        };
+        Ok(Adapter {
+            name: name.to_string(),
+            seq,
+            kind,
+            linked: None,
+        })
+    }
+
+    // adapter notation for logs and reports
+    pub fn spec(&self) -> String {
+        let part = |seq: &[u8], kind: AdapterType| match kind {
+            AdapterType::AnchoredFront => format!("^{}", String::from_utf8_lossy(seq)),
+            AdapterType::AnchoredBack => format!("{}$", String::from_utf8_lossy(seq)),
+            _ => String::from_utf8_lossy(seq).to_string(),
+        };
+        match &self.linked {
+            Some((seq, kind)) => format!("{}...{}", part(&self.seq, self.kind), part(seq, *kind)),
+            None => part(&self.seq, self.kind),
+        }
+    }
+}
+
+// sequence and type of one adapter part, ^ anchors 5' adapter and $ anchors 3' adapter
+fn split_anchor(part: &str, front: bool) -> Result<(Vec<u8>, AdapterType), String> {
+    let (seq, kind) = if let Some(seq) = part.strip_prefix('^') {
+        if !front {
+            return Err(format!("'^' is only allowed for 5' adapter: {}", part));
+        }
+        (seq, AdapterType::AnchoredFront)
+    } else if let Some(seq) = part.strip_suffix('$') {
+        if front {
+            return Err(format!("'$' is only allowed for 3' adapter: {}", part));
+        }
+        (seq, AdapterType::AnchoredBack)
+    } else if front {
+        (part, AdapterType::Front)
+    } else {
+        (part, AdapterType::Back)
+    };
+    if seq.is_empty() || !seq.bytes().all(|b| iupac_bits(b) != 0) {
+        return Err(format!("invalid adapter sequence: {}", part));
+    }
+    Ok((seq.as_bytes().to_vec(), kind))
+}
+
+impl Aligner {
+    // trim one adapter from read, anchored parts of a linked adapter are required
+    pub fn match_adapter(&self, adapter: &Adapter, read: &[u8]) -> Option<AdapterHit> {
+        let hit = self.locate(&adapter.seq, read, adapter.kind.ends());
+        let Some((back_seq, back_kind)) = &adapter.linked else {
+            return hit.map(|hit| {
+                let (start, end) = if adapter.kind.is_front() {
+                    (hit.read_end, read.len())
+                } else {
+                    (0, hit.read_start)
+                };
+                AdapterHit {
+                    start,
+                    end,
+                    matches: hit.matches,
+                    errors: hit.errors,
+                }
+            });
+        };
+
+        if hit.is_none() && adapter.kind.is_anchored() {
+            return None;
+        }
+        let start = hit.map_or(0, |x| x.read_end);
+        let back = self.locate(back_seq, &read[start..], back_kind.ends());
+        if (back.is_none() && back_kind.is_anchored()) || (hit.is_none() && back.is_none()) {
+            return None;
+        }
+        Some(AdapterHit {
+            start,
+            end: back.map_or(read.len(), |x| start + x.read_start),
+            matches: hit.map_or(0, |x| x.matches) + back.map_or(0, |x| x.matches),
+            errors: hit.map_or(0, |x| x.errors) + back.map_or(0, |x| x.errors),
+        })
+    }
+
+    // best hit across all adapters: most matched bases, then fewest errors, then input order
+    pub fn best_match(&self, adapters: &[Adapter], read: &[u8]) -> Option<(usize, AdapterHit)> {
+        let mut best: Option<(usize, AdapterHit)> = None;
+        for (idx, adapter) in adapters.iter().enumerate() {
+            if let Some(hit) = self.match_adapter(adapter, read) {
+                if best.is_none_or(|(_, b)| {
+                    hit.matches > b.matches || (hit.matches == b.matches && hit.errors < b.errors)
+                }) {
+                    best = Some((idx, hit));
+                }
+            }
+        }
+        best
+    }
+}
+
+// load adapter sequences from fasta file in file order, duplicate ids keep the first one
+fn load_adapters(seqfile: &String) -> Result<Vec<Adapter>, FqkitError> {
     let mut seqfile_reader = file_reader(Some(seqfile)).map(fasta::Reader::new)?;
     let mut faset = fasta::RecordSet::default();
-    let mut seqs = HashMap::new();
+    let mut ids = HashSet::new();
+    let mut adapters = vec![];
 
     while faset.fill(&mut seqfile_reader)? {
         for rec in faset.iter().map_while(Result::ok) {
-            if seqs.contains_key(rec.id()) {
+            if ids.contains(rec.id()) {
                 warn!(
                     "found duplicate sequence id: {}, keep first one",
                     std::str::from_utf8(rec.id())?
                 );
                 continue;
             } else {
-                seqs.entry(rec.id().to_owned())
-                    .or_insert(rec.seq().to_vec());
+                ids.insert(rec.id().to_owned());
+                let name = std::str::from_utf8(rec.id())?;
+                match Adapter::parse(name, std::str::from_utf8(rec.seq())?) {
+                    Ok(adapter) => adapters.push(adapter),
+                    Err(e) => {
+                        error!("{}, in file: {}", e, seqfile);
+                        std::process::exit(1);
+                    }
+                }
             }
         }
     }
 
-    if seqs.is_empty() {
+    if adapters.is_empty() {
         error!("{}", FqkitError::EmptyFile(seqfile.to_string()));
         std::process::exit(1);
     }
+    Ok(adapters)
+}
+
+// built-in adapters: library name, adapter name and sequence
+const ADAPTER_LIBRARY: [(&str, &str, &str); 5] = [
+    (
+        "truseq",
+        "truseq_read1",
+        "AGATCGGAAGAGCACACGTCTGAACTCCAGTCA",
+    ),
+    (
+        "truseq",
+        "truseq_read2",
+        "AGATCGGAAGAGCGTCGTGTAGGGAAAGAGTGT",
+    ),
+    ("nextera", "nextera", "CTGTCTCTTATACACATCT"),
+    ("smallrna", "smallrna", "TGGAATTCTCGGGTGCCAAGG"),
+    (
+        "nanopore",
+        "nanopore_ligation",
+        "AATGTACTTCGTTCAGTTACGTATTGCT",
+    ),
+];
+// number of reads sampled and adapter prefix length used in auto detection
+const DETECT_READS: usize = 10000;
+const DETECT_KMER: usize = 16;
+
+// adapters of a built-in library
+pub fn builtin_adapters(library: &str) -> Option<Vec<Adapter>> {
+    let adapters = ADAPTER_LIBRARY
+        .iter()
+        .filter(|(lib, _, _)| *lib == library)
+        .map(|(_, name, seq)| Adapter::new(name, seq.as_bytes()))
+        .collect::<Vec<Adapter>>();
+    if adapters.is_empty() {
+        None
+    } else {
+        Some(adapters)
+    }
+}
 
-    let mut fq_reader = file_reader(input).map(fastq::Reader::new)?;
-    let mut fq_writer = file_writer(out, compression_level, stdout_type)?;
-    let mut flag = false;
+// guess adapters in use by counting sampled reads containing the adapter prefix
+pub fn detect_adapters(input: &String) -> Result<Vec<Adapter>, FqkitError> {
+    let mut fq_reader = file_reader(Some(input)).map(fastq::Reader::new)?;
     let mut rset = fastq::RecordSet::default();
+    let mut counts = [0usize; ADAPTER_LIBRARY.len()];
+    let mut num = 0usize;
 
-    while rset.fill(&mut fq_reader)? {
+    'outer: while rset.fill(&mut fq_reader)? {
         for rec in rset.iter().map_while(Result::ok) {
-            let read_len = rec.seq().len();
-
-            for (_, seq) in seqs.iter() {
-                let pat = seq;
-                if read_len >= pat.len() {
-                    if left {
-                        let hanming = pat
-                            .iter()
-                            .zip(rec.seq().iter())
-                            .enumerate()
-                            .filter(|(_, (y, z))| y != z)
-                            .count();
-                        if hanming <= miss {
-                            write_record(
-                                &mut fq_writer,
-                                rec.id(),
-                                &rec.seq()[pat.len()..],
-                                &rec.qual()[pat.len()..],
-                            )?;
-                            flag = true;
-                            break;
-                        }
-                    } else {
-                        let idx = read_len - pat.len();
-                        let hanming = pat
-                            .iter()
-                            .zip(rec.seq()[idx..].iter())
-                            .enumerate()
-                            .filter(|(_, (y, z))| y != z)
-                            .count();
-                        if hanming <= miss {
-                            write_record(
-                                &mut fq_writer,
-                                rec.id(),
-                                &rec.seq()[0..idx],
-                                &rec.qual()[0..idx],
-                            )?;
-                            flag = true;
-                            break;
-                        }
-                    }
+            if num >= DETECT_READS {
+                break 'outer;
+            }
+            num += 1;
+            for (count, (_, _, seq)) in counts.iter_mut().zip(ADAPTER_LIBRARY.iter()) {
+                let kmer = &seq.as_bytes()[..DETECT_KMER];
+                if rec.seq().windows(DETECT_KMER).any(|x| x == kmer) {
+                    *count += 1;
                 }
             }
-            if flag {
-                flag = false;
-                continue;
+        }
+    }
+
+    // at least 0.1% of sampled reads
+    let min_count = (num / 1000).max(3);
+    let mut found = counts
+        .iter()
+        .zip(ADAPTER_LIBRARY.iter())
+        .filter(|(count, _)| **count >= min_count)
+        .collect::<Vec<_>>();
+    found.sort_by(|a, b| b.0.cmp(a.0));
+    if found.is_empty() {
+        warn!("no adapter detected in {} reads of file: {}", num, input);
+    }
+    Ok(found
+        .into_iter()
+        .map(|(count, (_, name, seq))| {
+            info!(
+                "detected adapter {} in {}/{} reads of file: {}",
+                name, count, num, input
+            );
+            Adapter::new(name, seq.as_bytes())
+        })
+        .collect())
+}
+
+// collect adapters from fasta file, built-in libraries (comma separated) and auto detection,
+// duplicate adapters keep the first one
+pub fn select_adapters(
+    seqfile: Option<&String>,
+    library: Option<&String>,
+    auto_detect: bool,
+    left: bool,
+    input: Option<&String>,
+    read2: Option<&String>,
+) -> Result<Vec<Adapter>, FqkitError> {
+    let mut adapters = vec![];
+    if let Some(seqfile) = seqfile {
+        adapters.extend(load_adapters(seqfile)?);
+    }
+    if let Some(library) = library {
+        for name in library.split(',').map(|x| x.trim()) {
+            if let Some(lib) = builtin_adapters(name) {
+                adapters.extend(lib);
             } else {
-                write_record(&mut fq_writer, rec.id(), rec.seq(), rec.qual())?;
+                error!(
+                    "invalid adapter library: {}, supported: truseq, nextera, smallrna, nanopore",
+                    name
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    if auto_detect {
+        let Some(input) = input else {
+            error!("adapter auto detection requires input fastq file, not stdin");
+            std::process::exit(1);
+        };
+        adapters.extend(detect_adapters(input)?);
+        if let Some(read2) = read2 {
+            adapters.extend(detect_adapters(read2)?);
+        }
+    }
+
+    // plain 3' adapters are treated as 5' adapters with -l/--left
+    if left {
+        for adapter in adapters.iter_mut() {
+            if adapter.kind == AdapterType::Back && adapter.linked.is_none() {
+                adapter.kind = AdapterType::Front;
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    adapters.retain(|x| seen.insert(x.spec()));
+    for adapter in adapters.iter() {
+        info!("adapter {}: {}", adapter.name, adapter.spec());
+    }
+    Ok(adapters)
+}
+
+// insert length of a read-through pair: the 3' end of reverse complemented read2
+// overlaps the 5' start of read1, and bases after the insert are adapters
+pub fn insert_by_overlap(
+    seq1: &[u8],
+    seq2: &[u8],
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+) -> Option<usize> {
+    let seq2_rev = reverse_complement(seq2);
+    find_overlap(&seq2_rev, seq1, min_overlap_len, max_mismatch_rate)
+        .map(|(insert, _)| insert)
+        .filter(|insert| *insert < seq1.len().max(seq2.len()))
+}
+
+// index of read count, trimmed reads (pairs) and pairs trimmed by overlap
+const TOTAL: usize = 0;
+const TRIMMED: usize = 1;
+const OVERLAP: usize = 2;
+
+#[derive(Clone)]
+struct AdapterTrimmer {
+    adapters: Arc<Vec<Adapter>>,
+    aligner: Aligner,
+    times: usize,
+    discard_untrimmed: bool,
+    // pair end insert detection, min overlap length and max mismatch rate
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+    buffer1: Vec<u8>,
+    buffer2: Vec<u8>,
+    count: [usize; 3],
+    // hits and trimmed bases of each adapter
+    hits: Vec<[usize; 2]>,
+    total_count: Arc<Mutex<[usize; 3]>>,
+    total_hits: Arc<Mutex<Vec<[usize; 2]>>>,
+    writer1: Arc<Mutex<Box<dyn Write + Send>>>,
+    writer2: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+    // reads without any adapter found
+    untrimmed_buffer1: Vec<u8>,
+    untrimmed_buffer2: Vec<u8>,
+    untrimmed_writer1: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+    untrimmed_writer2: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+impl AdapterTrimmer {
+    // kept range [start, end) of read after removing adapters up to `times` rounds
+    fn trim(&mut self, seq: &[u8]) -> (usize, usize) {
+        let (mut start, mut end) = (0, seq.len());
+        for _ in 0..self.times {
+            let Some((idx, hit)) = self.aligner.best_match(&self.adapters, &seq[start..end]) else {
+                break;
+            };
+            let (new_start, new_end) = (start + hit.start, start + hit.end);
+            self.hits[idx][0] += 1;
+            self.hits[idx][1] += (end - start) - (new_end - new_start);
+            (start, end) = (new_start, new_end);
+            if start == end {
+                break;
             }
         }
+        (start, end)
+    }
+
+    fn flush_batch(&mut self) -> Result<(), ProcessError> {
+        let mut total_count = self.total_count.lock();
+        for (t, c) in total_count.iter_mut().zip(self.count.iter()) {
+            *t += *c;
+        }
+        drop(total_count);
+        let mut total_hits = self.total_hits.lock();
+        for (t, c) in total_hits.iter_mut().zip(self.hits.iter()) {
+            t[0] += c[0];
+            t[1] += c[1];
+        }
+        drop(total_hits);
+
+        let mut writer1 = self.writer1.lock();
+        writer1.write_all(&self.buffer1)?;
+        writer1.flush()?;
+        if let Some(writer2) = &self.writer2 {
+            let mut writer2 = writer2.lock();
+            writer2.write_all(&self.buffer2)?;
+            writer2.flush()?;
+        }
+        if let Some(writer) = &self.untrimmed_writer1 {
+            let mut writer = writer.lock();
+            writer.write_all(&self.untrimmed_buffer1)?;
+            writer.flush()?;
+        }
+        if let Some(writer) = &self.untrimmed_writer2 {
+            let mut writer = writer.lock();
+            writer.write_all(&self.untrimmed_buffer2)?;
+            writer.flush()?;
+        }
+
+        // reset for next batch
+        self.buffer1.clear();
+        self.buffer2.clear();
+        self.untrimmed_buffer1.clear();
+        self.untrimmed_buffer2.clear();
+        self.count = [0; 3];
+        self.hits.iter_mut().for_each(|x| *x = [0; 2]);
+        Ok(())
+    }
+}
+
+impl ParallelProcessor for AdapterTrimmer {
+    fn process_record<Rf: Record>(&mut self, record: Rf) -> Result<(), ProcessError> {
+        let (start, end) = self.trim(record.seq());
+        self.count[TOTAL] += 1;
+        if end - start < record.seq().len() {
+            self.count[TRIMMED] += 1;
+        } else if self.untrimmed_writer1.is_some() || self.discard_untrimmed {
+            if self.untrimmed_writer1.is_some() {
+                write_record(
+                    &mut self.untrimmed_buffer1,
+                    record.id(),
+                    record.seq(),
+                    record.qual().unwrap(),
+                )?;
+            }
+            return Ok(());
+        }
+        write_record(
+            &mut self.buffer1,
+            record.id(),
+            &record.seq()[start..end],
+            &record.qual().unwrap()[start..end],
+        )?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+// pair end reads are cut at the insert end found by overlap, adapter sequences
+// are aligned to each mate if no overlap is found, a pair is untrimmed if neither mate is trimmed
+impl PairedParallelProcessor for AdapterTrimmer {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        let (len1, len2) = (rec1.seq().len(), rec2.seq().len());
+        let ((start1, end1), (start2, end2)) = if let Some(insert) = insert_by_overlap(
+            rec1.seq(),
+            rec2.seq(),
+            self.min_overlap_len,
+            self.max_mismatch_rate,
+        ) {
+            self.count[OVERLAP] += 1;
+            ((0, insert.min(len1)), (0, insert.min(len2)))
+        } else {
+            (self.trim(rec1.seq()), self.trim(rec2.seq()))
+        };
+        self.count[TOTAL] += 1;
+        if end1 - start1 < len1 || end2 - start2 < len2 {
+            self.count[TRIMMED] += 1;
+        } else if self.untrimmed_writer1.is_some() || self.discard_untrimmed {
+            if self.untrimmed_writer1.is_some() {
+                write_record(
+                    &mut self.untrimmed_buffer1,
+                    rec1.id(),
+                    rec1.seq(),
+                    rec1.qual().unwrap(),
+                )?;
+                write_record(
+                    &mut self.untrimmed_buffer2,
+                    rec2.id(),
+                    rec2.seq(),
+                    rec2.qual().unwrap(),
+                )?;
+            }
+            return Ok(());
+        }
+        write_record(
+            &mut self.buffer1,
+            rec1.id(),
+            &rec1.seq()[start1..end1],
+            &rec1.qual().unwrap()[start1..end1],
+        )?;
+        write_record(
+            &mut self.buffer2,
+            rec2.id(),
+            &rec2.seq()[start2..end2],
+            &rec2.qual().unwrap()[start2..end2],
+        )?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn cut_adapter(
+    input: Option<&String>,
+    read2: Option<&String>,
+    seqfile: Option<&String>,
+    library: Option<&String>,
+    auto_detect: bool,
+    left: bool,
+    mut aligner: Aligner,
+    miss: Option<usize>,
+    times: usize,
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+    discard_untrimmed: bool,
+    untrimmed: Option<&String>,
+    untrimmed2: Option<&String>,
+    json: Option<&String>,
+    ncpu: usize,
+    out: Option<&String>,
+    out2: Option<&String>,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), FqkitError> {
+    if read2.is_none() && seqfile.is_none() && library.is_none() && !auto_detect {
+        error!(
+            "one of -f/--fasta, -a/--adapter or -A/--auto-detect is required for single end reads"
+        );
+        std::process::exit(1);
+    }
+    let adapters = select_adapters(seqfile, library, auto_detect, left, input, read2)?;
+    if read2.is_none() && adapters.is_empty() {
+        error!("no adapter to trim");
+        std::process::exit(1);
+    }
+    // deprecated mismatch count, converted to error rate of the shortest adapter,
+    // half an error is added so a full length match allows exactly miss errors
+    if let Some(miss) = miss {
+        warn!("-m/--miss is deprecated, use -e/--error-rate instead");
+        if let Some(len) = adapters.iter().map(|x| x.seq.len()).min() {
+            aligner.max_error_rate = (miss as f64 + 0.5) / len as f64;
+        }
+    }
+    if times == 0 {
+        error!("-t/--times must be at least 1");
+        std::process::exit(1);
+    }
+    if read2.is_some() && (out.is_none() || out2.is_none()) {
+        error!("both -o/--out and -r/--out2 are required for pair end reads");
+        std::process::exit(1);
+    }
+    if read2.is_some() && untrimmed.is_some() != untrimmed2.is_some() {
+        error!("both -u/--untrimmed and -U/--untrimmed2 are required for pair end reads");
+        std::process::exit(1);
+    }
+    info!(
+        "max error rate: {}, min overlap: {}, allow indels: {}, times: {}",
+        aligner.max_error_rate, aligner.min_overlap, aligner.indels, times
+    );
+
+    let fq_reader1 = file_reader(input).map(fastq::Reader::new)?;
+    let writer2 = if read2.is_some() {
+        Some(Arc::new(Mutex::new(file_writer(
+            out2,
+            compression_level,
+            stdout_type,
+        )?)))
+    } else {
+        None
+    };
+    let untrimmed_writer1 = if let Some(untrimmed) = untrimmed {
+        Some(Arc::new(Mutex::new(file_writer(
+            Some(untrimmed),
+            compression_level,
+            stdout_type,
+        )?)))
+    } else {
+        None
+    };
+    let untrimmed_writer2 = if let (Some(untrimmed2), Some(_)) = (untrimmed2, read2) {
+        Some(Arc::new(Mutex::new(file_writer(
+            Some(untrimmed2),
+            compression_level,
+            stdout_type,
+        )?)))
+    } else {
+        None
+    };
+    let num = adapters.len();
+    let trimmer = AdapterTrimmer {
+        adapters: Arc::new(adapters),
+        aligner,
+        times,
+        discard_untrimmed,
+        min_overlap_len,
+        max_mismatch_rate,
+        buffer1: vec![],
+        buffer2: vec![],
+        count: [0; 3],
+        hits: vec![[0; 2]; num],
+        total_count: Arc::new(Mutex::new([0; 3])),
+        total_hits: Arc::new(Mutex::new(vec![[0; 2]; num])),
+        writer1: Arc::new(Mutex::new(file_writer(
+            out,
+            compression_level,
+            stdout_type,
+        )?)),
+        writer2,
+        untrimmed_buffer1: vec![],
+        untrimmed_buffer2: vec![],
+        untrimmed_writer1,
+        untrimmed_writer2,
+    };
+
+    if let Some(read2) = read2 {
+        let fq_reader2 = file_reader(Some(read2)).map(fastq::Reader::new)?;
+        fq_reader1.process_parallel_paired(fq_reader2, trimmer.clone(), ncpu)?;
+    } else {
+        fq_reader1.process_parallel(trimmer.clone(), ncpu)?;
+    }
+
+    let count = *trimmer.total_count.lock();
+    let total_hits = trimmer.total_hits.lock();
+    let unit = if read2.is_some() {
+        "read pairs"
+    } else {
+        "reads"
+    };
+    info!("total {}: {}", unit, count[TOTAL]);
+    info!("{} with adapter trimmed: {}", unit, count[TRIMMED]);
+    if let Some(untrimmed) = untrimmed {
+        info!(
+            "untrimmed {} written to file: {}, number: {}",
+            unit,
+            untrimmed,
+            count[TOTAL] - count[TRIMMED]
+        );
+    } else if discard_untrimmed {
+        info!(
+            "untrimmed {} discarded: {}",
+            unit,
+            count[TOTAL] - count[TRIMMED]
+        );
+    }
+    if read2.is_some() {
+        info!("read pairs trimmed by overlap: {}", count[OVERLAP]);
+    }
+    for (adapter, hit) in trimmer.adapters.iter().zip(total_hits.iter()) {
+        info!(
+            "adapter {}: {} hits, {} bases trimmed",
+            adapter.name, hit[0], hit[1]
+        );
+    }
+
+    if let Some(json) = json {
+        let mut report = json!({
+            "mode": if read2.is_some() { "pe" } else { "se" },
+            "total": count[TOTAL],
+            "trimmed": count[TRIMMED],
+        });
+        if read2.is_some() {
+            report["overlap"] = json!(count[OVERLAP]);
+        }
+        report["adapters"] = trimmer
+            .adapters
+            .iter()
+            .zip(total_hits.iter())
+            .map(|(adapter, hit)| {
+                json!({
+                    "name": adapter.name,
+                    "sequence": adapter.spec(),
+                    "hits": hit[0],
+                    "trimmed_bases": hit[1],
+                })
+            })
+            .collect();
+        let mut fo = file_writer(Some(json), compression_level, stdout_type)?;
+        serde_json::to_writer_pretty(&mut fo, &report).map_err(std::io::Error::from)?;
+        fo.write_all(b"\n")?;
+        fo.flush()?;
     }
-    fq_writer.flush()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIGNER: Aligner = Aligner {
+        max_error_rate: 0.1,
+        min_overlap: 3,
+        indels: true,
+    };
+    const ADAPTER: &[u8] = b"AGATCGGAAGAGC";
+
+    #[test]
+    fn back_full_and_internal() {
+        let hit = ALIGNER.locate(ADAPTER, b"TTTTTTTTTTAGATCGGAAGAGCCCCC", AlignEnds::BACK);
+        assert_eq!(hit.map(|x| (x.read_start, x.errors)), Some((10, 0)));
+        // one mismatch in 13 bases
+        let hit = ALIGNER.locate(ADAPTER, b"TTTTTTTTTTAGATCGCAAGAGC", AlignEnds::BACK);
+        assert_eq!(hit.map(|x| (x.read_start, x.errors)), Some((10, 1)));
+    }
+
+    #[test]
+    fn back_partial() {
+        let hit = ALIGNER.locate(ADAPTER, b"TTTTTTTTTTTTTTTAGATC", AlignEnds::BACK);
+        assert_eq!(hit.map(|x| (x.read_start, x.adapter_end)), Some((15, 5)));
+        // shorter than min overlap
+        let hit = ALIGNER.locate(ADAPTER, b"TTTTTTTTTTTTTTTTTTAG", AlignEnds::BACK);
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn back_indel() {
+        // one base deleted from adapter in read
+        let hit = ALIGNER.locate(ADAPTER, b"TTTTTTTTTTAGATCGAAGAGC", AlignEnds::BACK);
+        assert_eq!(hit.map(|x| (x.read_start, x.errors)), Some((10, 1)));
+        let no_indel = Aligner {
+            indels: false,
+            ..ALIGNER
+        };
+        let hit = no_indel.locate(ADAPTER, b"TTTTTTTTTTAGATCGAAGAGC", AlignEnds::BACK);
+        assert!(hit.is_none_or(|x| x.read_start > 10));
+    }
+
+    #[test]
+    fn pe_read_through() {
+        let insert = b"ACGTTGCAAGCTTCGATCGGATCCTAGCATGACTGATTGCA";
+        let mut read1 = insert.to_vec();
+        read1.extend_from_slice(b"AGATCGGAAGAGCACACG");
+        let mut read2 = reverse_complement(insert);
+        read2.extend_from_slice(b"AGATCGGAAGAGCGTCGT");
+        assert_eq!(
+            insert_by_overlap(&read1, &read2, 20, 0.1),
+            Some(insert.len())
+        );
+        // insert longer than reads
+        assert_eq!(
+            insert_by_overlap(&insert[..30], &reverse_complement(&insert[10..]), 20, 0.1),
+            None
+        );
+    }
+
+    #[test]
+    fn iupac_and_best_match() {
+        let aligner = Aligner {
+            max_error_rate: 0.0,
+            ..ALIGNER
+        };
+        let hit = aligner.locate(b"AGATCRGAAGAGC", b"TTTTTAGATCGGAAGAGC", AlignEnds::BACK);
+        assert_eq!(hit.map(|x| x.read_start), Some(5));
+        assert!(!base_match(b'R', b'C'));
+
+        let adapters = [
+            Adapter::new("short", b"AGATCGG"),
+            Adapter::new("long", ADAPTER),
+        ];
+        let hit = ALIGNER.best_match(&adapters, b"TTTTTAGATCGGAAGAGCTT");
+        assert_eq!(hit.map(|(idx, x)| (idx, x.end)), Some((1, 5)));
+    }
+
+    #[test]
+    fn anchored_and_linked() {
+        let front = Adapter::parse("f", "^ACGTACGT").unwrap();
+        assert_eq!(front.kind, AdapterType::AnchoredFront);
+        let hit = ALIGNER.match_adapter(&front, b"ACGTACGTTTTTTTTT");
+        assert_eq!(hit.map(|x| (x.start, x.end)), Some((8, 16)));
+        // anchored adapter must start at position 0
+        assert_eq!(ALIGNER.match_adapter(&front, b"GACGTACGTTTTTTTTT"), None);
+
+        let back = Adapter::parse("b", "GGCCAAGG$").unwrap();
+        assert_eq!(
+            ALIGNER
+                .match_adapter(&back, b"TTTTTTTTGGCCAAGG")
+                .map(|x| x.end),
+            Some(8)
+        );
+        assert_eq!(ALIGNER.match_adapter(&back, b"TTTTTTTTGGCCAAGGT"), None);
+
+        let linked = Adapter::parse("l", "^ACGTACGT...GGCCAAGG").unwrap();
+        assert_eq!(linked.spec(), "^ACGTACGT...GGCCAAGG");
+        let hit = ALIGNER.match_adapter(&linked, b"ACGTACGTTTTTTTTTGGCCAAGGAA");
+        assert_eq!(hit.map(|x| (x.start, x.end)), Some((8, 16)));
+        // optional 3' part
+        let hit = ALIGNER.match_adapter(&linked, b"ACGTACGTTTTTTTTT");
+        assert_eq!(hit.map(|x| (x.start, x.end)), Some((8, 16)));
+        // required 5' part
+        assert_eq!(ALIGNER.match_adapter(&linked, b"TTTTTTTTTTGGCCAAGG"), None);
+        assert!(Adapter::parse("bad", "ACGT$...ACGT").is_err());
+    }
+
+    #[test]
+    fn builtin_library() {
+        let truseq = builtin_adapters("truseq").unwrap();
+        assert_eq!(truseq.len(), 2);
+        assert!(truseq.iter().all(|x| x.seq.starts_with(b"AGATCGGAAGAGC")));
+        assert!(builtin_adapters("unknown").is_none());
+        assert!(
+            ADAPTER_LIBRARY
+                .iter()
+                .all(|(_, _, seq)| seq.len() >= DETECT_KMER)
+        );
+    }
+
+    #[test]
+    fn front_partial() {
+        let hit = ALIGNER.locate(ADAPTER, b"GAAGAGCTTTTTTTTTTTT", AlignEnds::FRONT);
+        assert_eq!(hit.map(|x| (x.read_end, x.adapter_start)), Some((7, 6)));
+    }
+}
@@ -0,0 +1,306 @@
+use super::{
+    barcode::hamming_dis,
+    misc::{check_synced, write_record},
+};
+use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
+use log::{error, info};
+use paraseq::fastq;
+use std::collections::HashMap;
+
+// one read, or both mates of a pair
+#[derive(Clone, Debug, Default)]
+struct Read {
+    id: Vec<Vec<u8>>,
+    seq: Vec<Vec<u8>>,
+    qual: Vec<Vec<u8>>,
+}
+
+impl Read {
+    fn qual_sum(&self, phred: u8) -> usize {
+        self.qual
+            .iter()
+            .flatten()
+            .map(|q| q.saturating_sub(phred) as usize)
+            .sum()
+    }
+}
+
+// umi in read name, RX:Z: tag in comment first, or suffix of read name after the last sep
+pub fn header_umi<'a>(id: &'a [u8], sep: &[u8]) -> Option<&'a [u8]> {
+    let mut fields = id.split(|x| x.is_ascii_whitespace());
+    let name = fields.next()?;
+    if let Some(tag) = fields.find(|x| x.starts_with(b"RX:Z:")) {
+        return Some(&tag[5..]);
+    }
+    if sep.is_empty() {
+        return None;
+    }
+    let pos = name.windows(sep.len()).rposition(|x| x == sep)?;
+    Some(&name[pos + sep.len()..]).filter(|x| !x.is_empty())
+}
+
+// UMI-tools directional clustering, umis sorted by count in descending order,
+// umi a absorbs umi b within mismatch if count(a) >= 2 * count(b) - 1, returns cluster id of each umi
+fn directional(umis: &[(&[u8], usize)], mismatch: usize) -> Vec<usize> {
+    let mut cluster = vec![usize::MAX; umis.len()];
+    let mut num = 0;
+    for i in 0..umis.len() {
+        if cluster[i] != usize::MAX {
+            continue;
+        }
+        cluster[i] = num;
+        let mut stack = vec![i];
+        while let Some(node) = stack.pop() {
+            let (umi, count) = umis[node];
+            for (j, (other, other_count)) in umis.iter().enumerate() {
+                if cluster[j] == usize::MAX
+                    && umi.len() == other.len()
+                    && hamming_dis(umi, other) <= mismatch
+                    && count + 1 >= 2 * other_count
+                {
+                    cluster[j] = num;
+                    stack.push(j);
+                }
+            }
+        }
+        num += 1;
+    }
+    cluster
+}
+
+// quality-weighted consensus of one mate over the length of the first read,
+// base quality is the quality sum of the winning base minus quality sum of other bases
+fn consensus(reads: &[(&[u8], &[u8])], phred: u8) -> (Vec<u8>, Vec<u8>) {
+    let len = reads[0].0.len();
+    let mut seq = Vec::with_capacity(len);
+    let mut qual = Vec::with_capacity(len);
+    for pos in 0..len {
+        // A C G T
+        let mut sums = [0usize; 4];
+        for (s, q) in reads.iter().filter(|x| x.0.len() > pos) {
+            let base = match s[pos].to_ascii_uppercase() {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => continue,
+            };
+            sums[base] += q[pos].saturating_sub(phred) as usize;
+        }
+        let total = sums.iter().sum::<usize>();
+        let (best, best_sum) = sums
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+            .unwrap();
+        if total == 0 {
+            seq.push(b'N');
+            qual.push(phred + 2);
+        } else {
+            seq.push(b"ACGT"[best]);
+            qual.push(phred + (2 * *best_sum as i64 - total as i64).clamp(2, 41) as u8);
+        }
+    }
+    (seq, qual)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn dedup_umi(
+    read1: &String,
+    read2: Option<&String>,
+    prefix: usize,
+    mismatch: usize,
+    sep: &str,
+    consensus_read: bool,
+    phred: u8,
+    out1: Option<&String>,
+    out2: Option<&String>,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), FqkitError> {
+    if ![33u8, 64u8].contains(&phred) {
+        error!("{}", FqkitError::InvalidPhredValue);
+        std::process::exit(1);
+    }
+    if read2.is_some() && (out1.is_none() || out2.is_none()) {
+        error!("both -f/--out1 and -r/--out2 are required for pair end reads");
+        std::process::exit(1);
+    }
+
+    // reads grouped by sequence prefix of each mate, umis are clustered within each group
+    let mut reads: Vec<Read> = vec![];
+    let mut groups: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    let mut umis: Vec<Vec<u8>> = vec![];
+
+    // mates are read in step, record counts and names are checked in each batch
+    let mut readers = vec![("read1", file_reader(Some(read1)).map(fastq::Reader::new)?)];
+    if let Some(read2) = read2 {
+        readers.push(("read2", file_reader(Some(read2)).map(fastq::Reader::new)?));
+    }
+    let names = readers.iter().map(|x| x.0).collect::<Vec<&str>>();
+    let mut rsets = readers
+        .iter()
+        .map(|_| fastq::RecordSet::default())
+        .collect::<Vec<fastq::RecordSet>>();
+    loop {
+        let mut filled = false;
+        for (rset, (_, reader)) in rsets.iter_mut().zip(readers.iter_mut()) {
+            filled |= rset.fill(reader)?;
+        }
+        if !filled {
+            break;
+        }
+        let records = rsets
+            .iter()
+            .map(|x| x.iter().collect::<Result<Vec<fastq::RefRecord>, _>>())
+            .collect::<Result<Vec<_>, _>>()?;
+        check_synced(&names, &records);
+        for i in 0..records[0].len() {
+            let mut read = Read::default();
+            let mut key = vec![];
+            for rec in records.iter().map(|x| &x[i]) {
+                key.extend_from_slice(&rec.seq()[..prefix.min(rec.seq().len())]);
+                key.push(b'\t');
+                read.id.push(rec.id().to_vec());
+                read.seq.push(rec.seq().to_vec());
+                read.qual.push(rec.qual().to_vec());
+            }
+            let Some(umi) = header_umi(&read.id[0], sep.as_bytes()) else {
+                error!(
+                    "no umi found in read name: {}, extract umi with subcommand umi first",
+                    String::from_utf8_lossy(&read.id[0])
+                );
+                std::process::exit(1);
+            };
+            umis.push(umi.to_vec());
+            groups.entry(key).or_default().push(reads.len());
+            reads.push(read);
+        }
+    }
+    info!("total reads (pairs): {}", reads.len());
+
+    // clusters of read indices, in order of first read
+    let mut clusters: Vec<Vec<usize>> = vec![];
+    for members in groups.values() {
+        let mut counts: HashMap<&[u8], usize> = HashMap::new();
+        for idx in members.iter() {
+            *counts.entry(&umis[*idx]).or_insert(0) += 1;
+        }
+        let mut counts = counts.into_iter().collect::<Vec<(&[u8], usize)>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let cluster = directional(&counts, mismatch);
+        let cluster_of = counts
+            .iter()
+            .zip(cluster.iter())
+            .map(|(x, c)| (x.0, *c))
+            .collect::<HashMap<&[u8], usize>>();
+
+        let mut first_seen: HashMap<usize, usize> = HashMap::new();
+        for idx in members.iter() {
+            let c = cluster_of[umis[*idx].as_slice()];
+            let slot = *first_seen.entry(c).or_insert_with(|| {
+                clusters.push(vec![]);
+                clusters.len() - 1
+            });
+            clusters[slot].push(*idx);
+        }
+    }
+    clusters.sort_by_key(|x| x[0]);
+
+    let mut writers = vec![file_writer(out1, compression_level, stdout_type)?];
+    if read2.is_some() {
+        writers.push(file_writer(out2, compression_level, stdout_type)?);
+    }
+    for members in clusters.iter() {
+        // representative read: highest quality sum, first read on tie
+        let best = members
+            .iter()
+            .map(|x| &reads[*x])
+            .enumerate()
+            .max_by(|a, b| {
+                a.1.qual_sum(phred)
+                    .cmp(&b.1.qual_sum(phred))
+                    .then(b.0.cmp(&a.0))
+            })
+            .unwrap()
+            .1;
+        for (mate, writer) in writers.iter_mut().enumerate() {
+            if consensus_read && members.len() > 1 {
+                let mut mates = vec![(best.seq[mate].as_slice(), best.qual[mate].as_slice())];
+                mates.extend(
+                    members
+                        .iter()
+                        .map(|x| &reads[*x])
+                        .filter(|x| !std::ptr::eq(*x, best))
+                        .map(|x| (x.seq[mate].as_slice(), x.qual[mate].as_slice())),
+                );
+                let (seq, qual) = consensus(&mates, phred);
+                write_record(writer, &best.id[mate], &seq, &qual)?;
+            } else {
+                write_record(writer, &best.id[mate], &best.seq[mate], &best.qual[mate])?;
+            }
+        }
+    }
+    for writer in writers.iter_mut() {
+        writer.flush()?;
+    }
+
+    info!("total unique reads (pairs): {}", clusters.len());
+    info!(
+        "duplication rate: {:.4}%",
+        if reads.is_empty() {
+            0.0
+        } else {
+            (reads.len() - clusters.len()) as f64 / reads.len() as f64 * 100.0
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn umi_in_header() {
+        assert_eq!(header_umi(b"r1_ACGT 1:N:0:1", b"_"), Some(&b"ACGT"[..]));
+        assert_eq!(
+            header_umi(b"r1 1:N:0:1 RX:Z:ACGT", b"_"),
+            Some(&b"ACGT"[..])
+        );
+        assert_eq!(header_umi(b"r1:ACGT", b":"), Some(&b"ACGT"[..]));
+        assert_eq!(header_umi(b"r1 1:N:0:1", b"_"), None);
+    }
+
+    #[test]
+    fn directional_clustering() {
+        // ACGT absorbs ACGA (10 >= 2 * 3 - 1), ACGA can not absorb ACCA (3 < 2 * 3 - 1)
+        let umis = [
+            (&b"ACGT"[..], 10),
+            (&b"ACGA"[..], 3),
+            (&b"ACCA"[..], 3),
+            (&b"TTTT"[..], 1),
+        ];
+        assert_eq!(directional(&umis, 1), vec![0, 0, 1, 2]);
+        assert_eq!(directional(&umis, 0), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn quality_weighted_consensus() {
+        let reads = [
+            (&b"ACGT"[..], &b"IIII"[..]),
+            (&b"ACGA"[..], &b"III#"[..]),
+            (&b"ACTA"[..], &b"II#5"[..]),
+        ];
+        let (seq, qual) = consensus(&reads, 33);
+        assert_eq!(seq, b"ACGT");
+        assert_eq!(qual, b"JJJ3");
+        // no base holds half of the quality, lowest quality is reported
+        let reads = [
+            (&b"A"[..], &b"I"[..]),
+            (&b"C"[..], &b"I"[..]),
+            (&b"G"[..], &b"5"[..]),
+        ];
+        assert_eq!(consensus(&reads, 33), (b"A".to_vec(), b"#".to_vec()));
+    }
+}
@@ -1,31 +1,171 @@
-use super::misc::write_record;
+use super::{
+    cutadapter::{Adapter, Aligner},
+    misc::write_record,
+    qtrim::PolyTail,
+};
 use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
 use log::{error, info};
 use paraseq::{
     fastq,
     fastx::Record,
-    parallel::{PairedParallelProcessor, PairedParallelReader, ProcessError},
+    parallel::{
+        PairedParallelProcessor, PairedParallelReader, ParallelProcessor, ParallelReader,
+        ProcessError,
+    },
 };
 use parking_lot::Mutex;
+use serde_json::json;
 use std::{io::Write, sync::Arc};
 
+// name of each filter criterion, in the order of FilterSeq::qc_fail
+const CRITERIA: [&str; 9] = [
+    "too_many_n",
+    "too_short",
+    "low_complexity",
+    "low_quality",
+    "poly_tail",
+    "too_long",
+    "high_n_ratio",
+    "too_many_errors",
+    "low_qual_bases",
+];
+type FailCount = [usize; CRITERIA.len()];
+
+// read complexity methods, all scores are scaled to 0~100, higher is more complex
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Complexity {
+    // percentage of bases different from the next base
+    Change,
+    // 100 - DUST score of trinucleotides scaled by its maximum
+    Dust,
+    // shannon entropy of trinucleotides scaled by its maximum
+    Entropy,
+}
+
+impl Complexity {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "change" => Complexity::Change,
+            "dust" => Complexity::Dust,
+            "entropy" => Complexity::Entropy,
+            _ => {
+                error!("invalid complexity method: {}", name);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn score(&self, seq: &[u8]) -> f64 {
+        match self {
+            Complexity::Change => {
+                if seq.len() < 2 {
+                    return 0.0;
+                }
+                seq.iter()
+                    .skip(1)
+                    .zip(seq.iter())
+                    .filter(|(q1, q2)| q1 != q2)
+                    .count() as f64
+                    / (seq.len() - 1) as f64
+                    * 100.0
+            }
+            Complexity::Dust => {
+                let (counts, num) = trinucleotide_count(seq);
+                if num < 2 {
+                    return 100.0;
+                }
+                // sum of c(c-1)/2 / (l-1) reaches l/2 for a homopolymer
+                let pairs = counts
+                    .iter()
+                    .map(|c| c * c.saturating_sub(1))
+                    .sum::<usize>();
+                100.0 - pairs as f64 / (num * (num - 1)) as f64 * 100.0
+            }
+            Complexity::Entropy => {
+                let (counts, num) = trinucleotide_count(seq);
+                if num < 2 {
+                    return 100.0;
+                }
+                let entropy = counts
+                    .iter()
+                    .filter(|c| **c > 0)
+                    .map(|c| {
+                        let p = *c as f64 / num as f64;
+                        -p * p.log2()
+                    })
+                    .sum::<f64>();
+                entropy / (num.min(64) as f64).log2() * 100.0
+            }
+        }
+    }
+}
+
+// counts of 64 trinucleotides and total number, triplets with non ACGT base are skipped
+fn trinucleotide_count(seq: &[u8]) -> ([usize; 64], usize) {
+    let mut counts = [0usize; 64];
+    let mut num = 0;
+    for triplet in seq.windows(3) {
+        let mut idx = 0;
+        for base in triplet {
+            idx = idx * 4
+                + match base.to_ascii_uppercase() {
+                    b'A' => 0,
+                    b'C' => 1,
+                    b'G' => 2,
+                    b'T' => 3,
+                    _ => 64,
+                };
+        }
+        if idx < 64 {
+            counts[idx] += 1;
+            num += 1;
+        }
+    }
+    (counts, num)
+}
+
+// optional limits, None to disable
+#[derive(Clone, Copy, Debug)]
+pub struct ReadLimit {
+    pub max_length: Option<usize>,
+    pub n_ratio: Option<f64>,
+    pub max_ee: Option<f64>,
+    pub min_qual: u8,
+    pub qual_percent: Option<f64>,
+}
+
 #[derive(Clone)]
 struct FilterSeq {
     count_n: usize,
     length: usize,
     complexity: usize,
+    method: Complexity,
     average_qual: u8,
+    poly: Option<PolyTail>,
+    limit: ReadLimit,
     phred: u8,
     buffer1: Vec<u8>,
     buffer2: Vec<u8>,
     failed_buffer: Vec<u8>,
-    pe_ok: usize,
-    pe_fail: usize,
-    total_pe_ok: Arc<Mutex<usize>>,
-    total_pe_fail: Arc<Mutex<usize>>,
+    singleton_buffer: Vec<u8>,
+    ok: usize,
+    fail: usize,
+    single: usize,
+    total_ok: Arc<Mutex<usize>>,
+    total_fail: Arc<Mutex<usize>>,
+    total_single: Arc<Mutex<usize>>,
     writer1: Arc<Mutex<Box<dyn Write + Send>>>,
-    writer2: Arc<Mutex<Box<dyn Write + Send>>>,
+    writer2: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
     failed_writer: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+    singleton_writer: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+    // pair policy 'either', the passing mate of a failed pair is written to singleton file
+    keep_either: bool,
+    tag: bool,
+    // 3' adapters trimmed before quality control
+    adapters: Arc<Vec<Adapter>>,
+    aligner: Aligner,
+    fail_count: [FailCount; 2],
+    total_fail_count: Arc<Mutex<[FailCount; 2]>>,
 }
 
 impl FilterSeq {
@@ -34,33 +174,53 @@ impl FilterSeq {
         count_n: usize,
         length: usize,
         complexity: usize,
+        method: Complexity,
         average_qual: u8,
+        poly: Option<PolyTail>,
+        adapters: Vec<Adapter>,
+        limit: ReadLimit,
         phred: u8,
         buffer1: Vec<u8>,
         buffer2: Vec<u8>,
         failed_buffer: Vec<u8>,
-        pe_ok: usize,
-        pe_fail: usize,
+        ok: usize,
+        fail: usize,
         output1: Box<dyn Write + Send>,
-        output2: Box<dyn Write + Send>,
+        output2: Option<Box<dyn Write + Send>>,
         failed_out: Option<Box<dyn Write + Send>>,
+        singleton_out: Option<Box<dyn Write + Send>>,
+        keep_either: bool,
+        tag: bool,
     ) -> Self {
         Self {
             count_n,
             length,
             complexity,
+            method,
             average_qual,
+            poly,
+            limit,
             phred,
             buffer1,
             buffer2,
             failed_buffer,
-            pe_ok,
-            pe_fail,
-            total_pe_ok: Arc::new(Mutex::new(0)),
-            total_pe_fail: Arc::new(Mutex::new(0)),
+            singleton_buffer: vec![],
+            ok,
+            fail,
+            single: 0,
+            total_ok: Arc::new(Mutex::new(0)),
+            total_fail: Arc::new(Mutex::new(0)),
+            total_single: Arc::new(Mutex::new(0)),
             writer1: Arc::new(Mutex::new(output1)),
-            writer2: Arc::new(Mutex::new(output2)),
+            writer2: output2.map(|output2| Arc::new(Mutex::new(output2))),
             failed_writer: failed_out.map(|failed_out| Arc::new(Mutex::new(failed_out))),
+            singleton_writer: singleton_out.map(|out| Arc::new(Mutex::new(out))),
+            keep_either,
+            tag,
+            adapters: Arc::new(adapters),
+            aligner: Aligner::default(),
+            fail_count: [[0; CRITERIA.len()]; 2],
+            total_fail_count: Arc::new(Mutex::new([[0; CRITERIA.len()]; 2])),
         }
     }
 
@@ -77,29 +237,86 @@ impl FilterSeq {
     }
 
     pub fn qc_complx(&self, seq: &[u8]) -> bool {
-        let complex = seq
-            .iter()
-            .skip(1)
-            .zip(seq.iter())
-            .filter(|(q1, q2)| q1 != q2)
-            .count() as f64
-            / (seq.len() - 1) as f64;
-        complex as usize >= self.complexity
+        self.method.score(seq) >= self.complexity as f64
+    }
+
+    pub fn qc_poly(&self, seq: &[u8]) -> bool {
+        self.poly
+            .as_ref()
+            .is_none_or(|poly| poly.tail_len(seq) == 0)
+    }
+
+    pub fn qc_max_length(&self, seq: &[u8]) -> bool {
+        self.limit.max_length.is_none_or(|max| seq.len() <= max)
+    }
+
+    pub fn qc_n_ratio(&self, seq: &[u8]) -> bool {
+        self.limit.n_ratio.is_none_or(|ratio| {
+            seq.is_empty()
+                || seq.iter().filter(|v| *v == &b'N').count() as f64 / seq.len() as f64 <= ratio
+        })
+    }
+
+    // max expected errors, sum of error probabilities, same as usearch -fastq_maxee
+    pub fn qc_expected_errors(&self, qual: &[u8]) -> bool {
+        self.limit.max_ee.is_none_or(|max_ee| {
+            qual.iter()
+                .map(|x| 10.0f64.powf((x - self.phred) as f64 / -10.0))
+                .sum::<f64>()
+                <= max_ee
+        })
     }
 
-    pub fn qc_all(&self, seq: &[u8], qual: &[u8]) -> bool {
-        self.qc_base_n(seq)
-            && self.qc_phread_mean(qual)
-            && self.qc_complx(seq)
-            && self.qc_length(seq)
+    // percentage of bases with quality value >= min qual
+    pub fn qc_qual_percent(&self, qual: &[u8]) -> bool {
+        self.limit.qual_percent.is_none_or(|percent| {
+            let num = qual
+                .iter()
+                .filter(|x| **x - self.phred >= self.limit.min_qual)
+                .count();
+            !qual.is_empty() && num as f64 / qual.len() as f64 * 100.0 >= percent
+        })
     }
 
-    pub fn write_record1<Rf: Record>(&mut self, record: Rf) -> std::io::Result<()> {
+    // failed state of each criterion in CRITERIA
+    pub fn qc_fail(&self, seq: &[u8], qual: &[u8]) -> [bool; CRITERIA.len()] {
+        [
+            !self.qc_base_n(seq),
+            !self.qc_length(seq),
+            !self.qc_complx(seq),
+            !self.qc_phread_mean(qual),
+            !self.qc_poly(seq),
+            !self.qc_max_length(seq),
+            !self.qc_n_ratio(seq),
+            !self.qc_expected_errors(qual),
+            !self.qc_qual_percent(qual),
+        ]
+    }
+
+    // check one read and count its failed criteria for mate 0 (read1) or 1 (read2)
+    pub fn qc_count(&mut self, seq: &[u8], qual: &[u8], mate: usize) -> [bool; CRITERIA.len()] {
+        let failed = self.qc_fail(seq, qual);
+        for (count, fail) in self.fail_count[mate].iter_mut().zip(failed.iter()) {
+            if *fail {
+                *count += 1;
+            }
+        }
+        failed
+    }
+
+    // read length after 3' adapter trimming
+    pub fn adapter_end(&self, seq: &[u8]) -> usize {
+        self.aligner
+            .best_match(&self.adapters, seq)
+            .map_or(seq.len(), |(_, hit)| hit.end)
+    }
+
+    pub fn write_record1<Rf: Record>(&mut self, record: Rf, end: usize) -> std::io::Result<()> {
         write_record(
             &mut self.buffer1,
             record.id(),
-            record.seq(),
-            record.qual().unwrap(),
+            &record.seq()[..end],
+            &record.qual().unwrap()[..end],
         )?;
         // self.buffer1.write_all(b"@")?;
         // self.buffer1.extend_from_slice(record.id());
@@ -111,12 +328,12 @@ impl FilterSeq {
         Ok(())
     }
 
-    pub fn write_record2<Rf: Record>(&mut self, record: Rf) -> std::io::Result<()> {
+    pub fn write_record2<Rf: Record>(&mut self, record: Rf, end: usize) -> std::io::Result<()> {
         write_record(
             &mut self.buffer2,
             record.id(),
-            record.seq(),
-            record.qual().unwrap(),
+            &record.seq()[..end],
+            &record.qual().unwrap()[..end],
         )?;
         // self.buffer2.write_all(b"@")?;
         // self.buffer2.extend_from_slice(record.id());
@@ -128,55 +345,67 @@ impl FilterSeq {
         Ok(())
     }
 
-    pub fn write_record_fail<Rf: Record>(&mut self, record: Rf) -> std::io::Result<()> {
-        write_record(
-            &mut self.failed_buffer,
-            record.id(),
-            record.seq(),
-            record.qual().unwrap(),
-        )?;
-        // self.failed_buffer.write_all(b"@")?;
-        // self.failed_buffer.extend_from_slice(record.id());
-        // self.failed_buffer.write_all(b"\n")?;
-        // self.failed_buffer.extend_from_slice(record.seq());
-        // self.failed_buffer.write_all(b"\n+\n")?;
-        // self.failed_buffer.extend_from_slice(record.qual().unwrap());
-        // self.failed_buffer.write_all(b"\n")?;
+    // failed reads, the header is tagged with failed criteria if required
+    pub fn write_record_fail<Rf: Record>(
+        &mut self,
+        record: Rf,
+        end: usize,
+        failed: &[bool; CRITERIA.len()],
+    ) -> std::io::Result<()> {
+        if self.tag {
+            let reason = CRITERIA
+                .iter()
+                .zip(failed.iter())
+                .filter(|(_, fail)| **fail)
+                .map(|(name, _)| *name)
+                .collect::<Vec<&str>>();
+            let reason = if reason.is_empty() {
+                "mate_failed".to_string()
+            } else {
+                reason.join(",")
+            };
+            let mut id = record.id().to_vec();
+            id.extend_from_slice(format!(" reason={}", reason).as_bytes());
+            write_record(
+                &mut self.failed_buffer,
+                &id,
+                &record.seq()[..end],
+                &record.qual().unwrap()[..end],
+            )?;
+        } else {
+            write_record(
+                &mut self.failed_buffer,
+                record.id(),
+                &record.seq()[..end],
+                &record.qual().unwrap()[..end],
+            )?;
+        }
         Ok(())
     }
 }
 
-impl PairedParallelProcessor for FilterSeq {
-    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
-        if self.qc_all(rec1.seq(), rec1.qual().unwrap())
-            && self.qc_all(rec2.seq(), rec2.qual().unwrap())
-        {
-            self.write_record1(rec1)?;
-            self.write_record2(rec2)?;
-            self.pe_ok += 1;
-        } else {
-            if self.failed_writer.is_some() {
-                self.write_record_fail(rec1)?;
-                self.write_record_fail(rec2)?;
+impl FilterSeq {
+    // write buffered reads and counters of this batch
+    fn flush_batch(&mut self) -> std::io::Result<()> {
+        *self.total_ok.lock() += self.ok;
+        *self.total_fail.lock() += self.fail;
+        *self.total_single.lock() += self.single;
+        let mut total_fail_count = self.total_fail_count.lock();
+        for (total, count) in total_fail_count.iter_mut().zip(self.fail_count.iter()) {
+            for (t, c) in total.iter_mut().zip(count.iter()) {
+                *t += *c;
             }
-            self.pe_fail += 1;
         }
-        Ok(())
-    }
+        drop(total_fail_count);
 
-    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
         let mut writer1 = self.writer1.lock();
-        let mut writer2 = self.writer2.lock();
-        let mut pe_ok = self.total_pe_ok.lock();
-        let mut pe_fail = self.total_pe_fail.lock();
-
-        *pe_ok += self.pe_ok;
-        *pe_fail += self.pe_fail;
-
         writer1.write_all(&self.buffer1)?;
-        writer2.write_all(&self.buffer2)?;
         writer1.flush()?;
-        writer2.flush()?;
+        if let Some(writer2) = &self.writer2 {
+            let mut writer2 = writer2.lock();
+            writer2.write_all(&self.buffer2)?;
+            writer2.flush()?;
+        }
 
         if let Some(failed_writer) = &self.failed_writer {
             let mut writer = failed_writer.lock();
@@ -185,12 +414,85 @@ impl PairedParallelProcessor for FilterSeq {
             // reset for next batch
             self.failed_buffer.clear();
         }
+        if let Some(singleton_writer) = &self.singleton_writer {
+            let mut writer = singleton_writer.lock();
+            writer.write_all(&self.singleton_buffer)?;
+            writer.flush()?;
+            self.singleton_buffer.clear();
+        }
 
         // reset for next batch
         self.buffer1.clear();
         self.buffer2.clear();
-        self.pe_ok = 0;
-        self.pe_fail = 0;
+        self.ok = 0;
+        self.fail = 0;
+        self.single = 0;
+        self.fail_count = [[0; CRITERIA.len()]; 2];
+        Ok(())
+    }
+}
+
+impl ParallelProcessor for FilterSeq {
+    fn process_record<Rf: Record>(&mut self, record: Rf) -> Result<(), ProcessError> {
+        let end = self.adapter_end(record.seq());
+        let failed = self.qc_count(&record.seq()[..end], &record.qual().unwrap()[..end], 0);
+        if !failed.contains(&true) {
+            self.write_record1(record, end)?;
+            self.ok += 1;
+        } else {
+            if self.failed_writer.is_some() {
+                self.write_record_fail(record, end, &failed)?;
+            }
+            self.fail += 1;
+        }
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+impl PairedParallelProcessor for FilterSeq {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        let (end1, end2) = (self.adapter_end(rec1.seq()), self.adapter_end(rec2.seq()));
+        let failed1 = self.qc_count(&rec1.seq()[..end1], &rec1.qual().unwrap()[..end1], 0);
+        let failed2 = self.qc_count(&rec2.seq()[..end2], &rec2.qual().unwrap()[..end2], 1);
+        let (pass1, pass2) = (!failed1.contains(&true), !failed2.contains(&true));
+        if pass1 && pass2 {
+            self.write_record1(rec1, end1)?;
+            self.write_record2(rec2, end2)?;
+            self.ok += 1;
+        } else if (pass1 || pass2) && self.keep_either {
+            // keep the surviving mate as singleton, a failed mate is never written as clean read
+            let (pass, pass_end, fail, fail_end, failed) = if pass1 {
+                (rec1, end1, rec2, end2, failed2)
+            } else {
+                (rec2, end2, rec1, end1, failed1)
+            };
+            write_record(
+                &mut self.singleton_buffer,
+                pass.id(),
+                &pass.seq()[..pass_end],
+                &pass.qual().unwrap()[..pass_end],
+            )?;
+            if self.failed_writer.is_some() {
+                self.write_record_fail(fail, fail_end, &failed)?;
+            }
+            self.single += 1;
+        } else {
+            if self.failed_writer.is_some() {
+                self.write_record_fail(rec1, end1, &failed1)?;
+                self.write_record_fail(rec2, end2, &failed2)?;
+            }
+            self.fail += 1;
+        }
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
         Ok(())
     }
 }
@@ -198,16 +500,24 @@ impl PairedParallelProcessor for FilterSeq {
 #[allow(clippy::too_many_arguments)]
 pub fn filter_fastq(
     read1: &String,
-    read2: &String,
+    read2: Option<&String>,
     nbase: usize,
     length: usize,
     complexity: u32,
+    method: Complexity,
     average_qual: u8,
+    poly: Option<PolyTail>,
+    limit: ReadLimit,
+    adapters: Vec<Adapter>,
     phred: u8,
+    pair_policy: &str,
     ncpu: usize,
     failed: Option<&String>,
-    out1: &String,
-    out2: &String,
+    singleton: Option<&String>,
+    tag: bool,
+    json: Option<&String>,
+    out1: Option<&String>,
+    out2: Option<&String>,
     compression_level: u32,
     stdout_type: char,
 ) -> Result<(), FqkitError> {
@@ -216,19 +526,46 @@ pub fn filter_fastq(
         std::process::exit(1);
     }
 
+    if read2.is_some() && (out1.is_none() || out2.is_none()) {
+        error!("both -f/--out1 and -r/--out2 are required for pair end reads");
+        std::process::exit(1);
+    }
+    if read2.is_none() && (pair_policy == "either" || singleton.is_some()) {
+        error!("-m/--pair-policy either and -s/--singleton are only for pair end reads");
+        std::process::exit(1);
+    }
+    let keep_either = pair_policy == "either";
+    if read2.is_some() && keep_either != singleton.is_some() {
+        error!(
+            "pair policy 'either' and -s/--singleton file for the passing mate must be given together"
+        );
+        std::process::exit(1);
+    }
     let fq_reader1 = file_reader(Some(read1)).map(fastq::Reader::new)?;
-    let fq_reader2 = file_reader(Some(read2)).map(fastq::Reader::new)?;
-    let out_writer1 = file_writer(Some(out1), compression_level, stdout_type)?;
-    let out_writer2 = file_writer(Some(out2), compression_level, stdout_type)?;
+    let out_writer1 = file_writer(out1, compression_level, stdout_type)?;
+    let out_writer2 = if read2.is_some() {
+        Some(file_writer(out2, compression_level, stdout_type)?)
+    } else {
+        None
+    };
 
     let failed_writer = if let Some(failed) = failed {
         Some(file_writer(Some(failed), compression_level, stdout_type)?)
     } else {
         None
     };
+    let singleton_writer = if let Some(singleton) = singleton {
+        Some(file_writer(
+            Some(singleton),
+            compression_level,
+            stdout_type,
+        )?)
+    } else {
+        None
+    };
 
     let complex = complexity as usize;
-    let (pe_ok, pe_fail) = (0usize, 0usize);
+    let (ok, fail) = (0usize, 0usize);
     let buffer1 = vec![];
     let buffer2 = vec![];
     let failed_buffer = vec![];
@@ -237,24 +574,80 @@ pub fn filter_fastq(
         nbase,
         length,
         complex,
+        method,
         average_qual,
+        poly,
+        adapters,
+        limit,
         phred,
         buffer1,
         buffer2,
         failed_buffer,
-        pe_ok,
-        pe_fail,
+        ok,
+        fail,
         out_writer1,
         out_writer2,
         failed_writer,
+        singleton_writer,
+        keep_either,
+        tag,
     );
     // run the filter
-    fq_reader1.process_parallel_paired(fq_reader2, filters.clone(), ncpu)?;
+    let mates = if let Some(read2) = read2 {
+        let fq_reader2 = file_reader(Some(read2)).map(fastq::Reader::new)?;
+        fq_reader1.process_parallel_paired(fq_reader2, filters.clone(), ncpu)?;
+
+        let pe_ok = filters.total_ok.lock();
+        let pe_fail = filters.total_fail.lock();
+        info!("total clean pe reads number (r1+r2): {}", *pe_ok * 2);
+        info!("total failed pe reads number (r1+r2): {}", *pe_fail * 2);
+        if singleton.is_some() {
+            info!(
+                "total singleton reads number: {}",
+                *filters.total_single.lock()
+            );
+        }
+        vec!["read1", "read2"]
+    } else {
+        fq_reader1.process_parallel(filters.clone(), ncpu)?;
+
+        info!("total clean reads number: {}", *filters.total_ok.lock());
+        info!("total failed reads number: {}", *filters.total_fail.lock());
+        vec!["read"]
+    };
+
+    let total_fail_count = filters.total_fail_count.lock();
+    for (mate, count) in mates.iter().zip(total_fail_count.iter()) {
+        for (name, num) in CRITERIA.iter().zip(count.iter()) {
+            info!("{} failed by {}: {}", mate, name, num);
+        }
+    }
+
+    if let Some(json) = json {
+        let (ok, fail) = (*filters.total_ok.lock(), *filters.total_fail.lock());
+        let single = *filters.total_single.lock();
+        let mut report = json!({
+            "mode": if read2.is_some() { "pe" } else { "se" },
+            "total": ok + fail + single,
+            "passed": ok,
+            "failed": fail,
+        });
+        if read2.is_some() {
+            report["singleton"] = json!(single);
+        }
+        for (mate, count) in mates.iter().zip(total_fail_count.iter()) {
+            report[mate] = CRITERIA
+                .iter()
+                .zip(count.iter())
+                .map(|(name, num)| (name.to_string(), json!(num)))
+                .collect();
+        }
+        let mut fo = file_writer(Some(json), compression_level, stdout_type)?;
+        serde_json::to_writer_pretty(&mut fo, &report).map_err(std::io::Error::from)?;
+        fo.write_all(b"\n")?;
+        fo.flush()?;
+    }
 
-    let pe_ok = filters.total_pe_ok.lock();
-    let pe_fail = filters.total_pe_fail.lock();
-    info!("total clean pe reads number (r1+r2): {}", *pe_ok * 2);
-    info!("total failed pe reads number (r1+r2): {}", *pe_fail * 2);
     Ok(())
 }
 
@@ -267,3 +660,90 @@ fn phred_mean(qual: &[u8], phred: u8) -> u8 {
 
     (-10.0f64 * ave_error.log10()).round() as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_mate_fails() {
+        let records = |text: &str| {
+            let mut reader = fastq::Reader::new(std::io::Cursor::new(text.as_bytes().to_vec()));
+            let mut rset = fastq::RecordSet::default();
+            rset.fill(&mut reader).unwrap();
+            rset
+        };
+        let (rset1, rset2) = (
+            records("@r1\nACGTTGCAAG\n+\nIIIIIIIIII\n"),
+            records("@r1\nACGT\n+\nIIII\n"),
+        );
+        // pair policy any, with and without a singleton writer, and pair policy either
+        for (keep_either, singleton) in [(false, false), (false, true), (true, true)] {
+            let mut filters = FilterSeq::new(
+                10,
+                8,
+                0,
+                Complexity::Change,
+                0,
+                None,
+                vec![],
+                ReadLimit {
+                    max_length: None,
+                    n_ratio: None,
+                    max_ee: None,
+                    min_qual: 0,
+                    qual_percent: None,
+                },
+                33,
+                vec![],
+                vec![],
+                vec![],
+                0,
+                0,
+                Box::new(std::io::sink()),
+                Some(Box::new(std::io::sink())),
+                None,
+                singleton.then(|| Box::new(std::io::sink()) as Box<dyn Write + Send>),
+                keep_either,
+                false,
+            );
+            let rec1 = rset1.iter().next().unwrap().unwrap();
+            let rec2 = rset2.iter().next().unwrap().unwrap();
+            filters.process_record_pair(rec1, rec2).unwrap();
+            // the short read2 fails, nothing is written as clean pair
+            assert!(filters.buffer1.is_empty() && filters.buffer2.is_empty());
+            assert_eq!(filters.ok, 0);
+            if keep_either {
+                assert_eq!(
+                    filters.singleton_buffer,
+                    b"@r1\nACGTTGCAAG\n+\nIIIIIIIIII\n"
+                );
+                assert_eq!(filters.single, 1);
+            } else {
+                assert!(filters.singleton_buffer.is_empty());
+                assert_eq!(filters.fail, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn complexity_change() {
+        let seq = b"AAAATTTTTTTTTTTTTTTTTTTTTGGGGGGGGGGGGGGGGGGGGGGCCCC";
+        assert!((Complexity::Change.score(seq) - 6.0).abs() < 1e-9);
+        assert_eq!(Complexity::Change.score(b"ACGT"), 100.0);
+        assert_eq!(Complexity::Change.score(b"A"), 0.0);
+    }
+
+    #[test]
+    fn complexity_trinucleotide() {
+        let homo = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        let repeat = b"ACGACGACGACGACGACGACGACGACGACG";
+        let random = b"ACGTTGCAAGCTTCGATCGGATCCTAGCATGACTGA";
+        for method in [Complexity::Dust, Complexity::Entropy] {
+            assert!(method.score(homo) < 1e-9);
+            assert!(method.score(homo) < method.score(repeat));
+            assert!(method.score(repeat) < method.score(random));
+            assert!(method.score(random) <= 100.0);
+        }
+    }
+}
@@ -8,14 +8,14 @@ use paraseq::{
     parallel::{ParallelProcessor, ParallelReader, ProcessError},
 };
 use parking_lot::Mutex;
-use plotters::prelude::*;
+use plotters::{coord::Shift, prelude::*};
 use std::collections::HashMap;
 use std::sync::Arc;
 
 type Gctype = HashMap<u64, usize>;
 
 #[derive(Clone)]
-struct Gchash {
+pub struct Gchash {
     thread_hash: Gctype,
     glob_hash: Arc<Mutex<Gctype>>,
 }
@@ -27,18 +27,26 @@ impl Gchash {
             glob_hash: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+
+    pub fn add_seq(&mut self, seq: &[u8]) {
+        let gc_count = seq.iter().filter(|x| *x == &b'G' || *x == &b'C').count();
+        let gc_ratio = (gc_count as f64 / seq.len() as f64 * 100.0).round() as u64;
+        *self.thread_hash.entry(gc_ratio).or_insert(0) += 1usize;
+    }
+
+    // reads percent of each GC content from 0 to 100
+    pub fn gc_rate(&self) -> Vec<f32> {
+        let df_hash = self.glob_hash.lock();
+        let total = df_hash.values().sum::<usize>() as f32;
+        (0..=100)
+            .map(|i| (*df_hash.get(&i).unwrap_or(&0) as f32 * 10000.0 / total).round() / 100.0)
+            .collect()
+    }
 }
 
 impl ParallelProcessor for Gchash {
     fn process_record<Rf: Record>(&mut self, record: Rf) -> Result<(), ProcessError> {
-        let gc_count = record
-            .seq()
-            .iter()
-            .filter(|x| *x == &b'G' || *x == &b'C')
-            .count();
-        let gc_ratio = (gc_count as f64 / record.seq().len() as f64 * 100.0).round() as u64;
-        *self.thread_hash.entry(gc_ratio).or_insert(0) += 1usize;
-
+        self.add_seq(record.seq());
         Ok(())
     }
 
@@ -137,85 +145,71 @@ fn plot_gc(
 
     if types == "png" {
         let png = BitMapBackend::new(&name, (width as u32, height as u32)).into_drawing_area();
-        png.fill(&WHITE)?;
-
-        let mut charts = ChartBuilder::on(&png)
-            .margin(10)
-            .caption("GC distrbution plot", ("sans-serif", 30).into_font())
-            .x_label_area_size(40)
-            .y_label_area_size(40)
-            //.build_cartesian_2d(0..100, 0..ylim)?;
-            .build_cartesian_2d(0.0..100f32, 0.0..ylim as f32)?;
-
-        charts
-            .configure_mesh()
-            .x_labels(20)
-            .x_desc("GC CONTENT")
-            .x_label_formatter(&|x| format!("{:.0}%", x))
-            .y_labels(20)
-            .y_label_formatter(&|x| format!("{:.0}%", x))
-            .y_desc("percent")
-            .draw()?;
-
-        charts
-            .draw_series(
-                AreaSeries::new(
-                    (0..).zip(data.iter()).map(|(x, y)| (x as f32, *y)),
-                    0.,
-                    GREEN.mix(0.5),
-                )
-                .border_style(GREEN),
-            )?
-            .label("GC content")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
-
-        //charts.draw_series( LineSeries::new((0..).zip(data.iter()).map(|(x,y)| (x as f32, *y)), &BLACK) )?;
-
-        charts
-            .configure_series_labels()
-            .background_style(WHITE.mix(0.9))
-            .border_style(BLACK)
-            .position(SeriesLabelPosition::UpperRight)
-            .draw()?;
+        draw_gc(&png, &data, ylim as f32)?;
     } else {
         let svg = SVGBackend::new(&name, (width as u32, height as u32)).into_drawing_area();
-        svg.fill(&WHITE)?;
-
-        let mut charts = ChartBuilder::on(&svg)
-            .margin(10)
-            .caption("GC distrbution plot", ("sans-serif", 30).into_font())
-            .x_label_area_size(40)
-            .y_label_area_size(40)
-            .build_cartesian_2d(0.0..100f32, 0.0..ylim as f32)?;
-
-        charts
-            .configure_mesh()
-            .x_labels(20)
-            .x_desc("GC CONTENT")
-            .x_label_formatter(&|x| format!("{:.0}%", x))
-            .y_labels(20)
-            .y_label_formatter(&|x| format!("{:.0}%", x))
-            .y_desc("percent")
-            .draw()?;
-
-        charts
-            .draw_series(
-                AreaSeries::new(
-                    (0..).zip(data.iter()).map(|(x, y)| (x as f32, *y)),
-                    0.,
-                    GREEN.mix(0.5),
-                )
-                .border_style(GREEN),
-            )?
-            .label("GC content")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
-
-        charts
-            .configure_series_labels()
-            .background_style(WHITE.mix(0.9))
-            .border_style(BLACK)
-            .position(SeriesLabelPosition::UpperRight)
-            .draw()?;
+        draw_gc(&svg, &data, ylim as f32)?;
     }
     Ok(())
 }
+
+// GC content line plot rendered into a svg string
+pub fn gc_plot_svg(data: &[f32], width: usize, height: usize, ylim: f32) -> Result<String, Error> {
+    let mut buf = String::new();
+    {
+        let svg =
+            SVGBackend::with_string(&mut buf, (width as u32, height as u32)).into_drawing_area();
+        draw_gc(&svg, data, ylim)?;
+    }
+    Ok(buf)
+}
+
+fn draw_gc<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &[f32],
+    ylim: f32,
+) -> Result<(), Error>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let mut charts = ChartBuilder::on(root)
+        .margin(10)
+        .caption("GC distrbution plot", ("sans-serif", 30).into_font())
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0.0..100f32, 0.0..ylim)?;
+
+    charts
+        .configure_mesh()
+        .x_labels(20)
+        .x_desc("GC CONTENT")
+        .x_label_formatter(&|x| format!("{:.0}%", x))
+        .y_labels(20)
+        .y_label_formatter(&|x| format!("{:.0}%", x))
+        .y_desc("percent")
+        .draw()?;
+
+    charts
+        .draw_series(
+            AreaSeries::new(
+                (0..).zip(data.iter()).map(|(x, y)| (x as f32, *y)),
+                0.,
+                GREEN.mix(0.5),
+            )
+            .border_style(GREEN),
+        )?
+        .label("GC content")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
+
+    charts
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.9))
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperRight)
+        .draw()?;
+    root.present()?;
+
+    Ok(())
+}
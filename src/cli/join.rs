@@ -21,6 +21,40 @@ impl Count {
     }
 }
 
+// find the longest overlap between the 3' end of read1 and the 5' end of reverse complemented read2,
+// return the overlap length and mismatch count in overlap region
+pub fn find_overlap(
+    seq1: &[u8],
+    seq2_rev: &[u8],
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+) -> Option<(usize, usize)> {
+    let max_overlap_len = seq1.len().min(seq2_rev.len());
+    if min_overlap_len > max_overlap_len {
+        return None;
+    }
+    for overlap_len in (min_overlap_len.max(1)..=max_overlap_len).rev() {
+        // overlap region in PE reads
+        let over1 = &seq1[seq1.len() - overlap_len..];
+        let over2 = &seq2_rev[..overlap_len];
+
+        let mismatch = over1
+            .iter()
+            .zip(over2.iter())
+            .filter(|(x, y)| x != y)
+            .count();
+        let mismatch_rate = mismatch as f64 / overlap_len as f64;
+
+        // mismatch count too much
+        if max_mismatch_rate < mismatch_rate {
+            continue;
+        }
+        // pe reads overlaped
+        return Some((overlap_len, mismatch));
+    }
+    None
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn join_overlap(
     read1: &str,
@@ -48,38 +82,16 @@ pub fn join_overlap(
             .zip(rset2.iter().map_while(Result::ok))
         {
             count.count_total += 1;
-            let max_overlap_len = rec1.seq().len().min(rec2.seq().len());
             let rec2_seq_rev = reverse_complement(rec2.seq());
 
-            let mut fine_overlap_len = 0;
-            if min_overlap_len <= max_overlap_len {
-                for overlap_len in (min_overlap_len..=max_overlap_len).rev() {
-                    assert!(rec1.seq().len() >= overlap_len && rec2_seq_rev.len() >= overlap_len);
-                    // overlap region in PE reads
-                    let over1 = &rec1.seq()[rec1.seq().len() - overlap_len..];
-                    let over2 = &rec2_seq_rev[..overlap_len];
-
-                    let mismatch = over1
-                        .iter()
-                        .zip(over2.iter())
-                        .filter(|(x, y)| x != y)
-                        .count();
-                    let mismatch_rate = mismatch as f64 / overlap_len as f64;
-
-                    // mismatch count too much
-                    if max_mismatch_rate < mismatch_rate {
-                        continue;
-                    }
-                    // overlap length is too short
-                    if overlap_len < min_overlap_len {
-                        continue;
-                    }
-                    // pe reads overlaped
-                    fine_overlap_len = overlap_len;
-                    count.count_miss_overlap += mismatch;
-                    break;
-                }
-            }
+            let (fine_overlap_len, mismatch) = find_overlap(
+                rec1.seq(),
+                &rec2_seq_rev,
+                min_overlap_len,
+                max_mismatch_rate,
+            )
+            .unwrap_or((0, 0));
+            count.count_miss_overlap += mismatch;
 
             // build longer single read
             if fine_overlap_len > 0 {
@@ -79,7 +79,7 @@ pub fn fq_length(
     fo.flush()?;
     info!(
         "total scan reads number: {}",
-        reads_len.iter().map(|(_, v)| *v).sum::<usize>()
+        reads_len.values().sum::<usize>()
     );
 
     Ok(())
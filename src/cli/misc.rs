@@ -1,3 +1,5 @@
+use log::error;
+use paraseq::fastq::RefRecord;
 use std::io::{Result, Write};
 
 pub fn write_record<W>(writer: &mut W, id: &[u8], seq: &[u8], qual: &[u8]) -> Result<()>
@@ -14,6 +16,43 @@ where
     Ok(())
 }
 
+// read name without comment and mate suffix like /1 /2
+pub fn read_name(id: &[u8]) -> &[u8] {
+    let name = id.split(|x| x.is_ascii_whitespace()).next().unwrap_or(id);
+    match name {
+        [head @ .., b'/', b'1'..=b'3'] => head,
+        _ => name,
+    }
+}
+
+// batches of read1, read2 and index files must have the same number of records with the same
+// read names, the first batch is the reference
+pub fn check_synced(names: &[&str], records: &[Vec<RefRecord>]) {
+    for (name, recs) in names.iter().zip(records.iter()).skip(1) {
+        if recs.len() != records[0].len() {
+            error!(
+                "{} file has a different number of records from {} file",
+                name, names[0]
+            );
+            std::process::exit(1);
+        }
+        if let Some((rec0, rec)) = records[0]
+            .iter()
+            .zip(recs.iter())
+            .find(|(a, b)| read_name(a.id()) != read_name(b.id()))
+        {
+            error!(
+                "read name {} in {} file does not match {} in {} file",
+                String::from_utf8_lossy(rec.id()),
+                name,
+                String::from_utf8_lossy(rec0.id()),
+                names[0]
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
 pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
     seq.iter()
         .rev()
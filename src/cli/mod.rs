@@ -1,6 +1,8 @@
 pub mod barcode;
+pub mod cellbc;
 pub mod concat;
 pub mod cutadapter;
+pub mod dedup;
 pub mod filter;
 pub mod flatten;
 pub mod fq2fa;
@@ -15,9 +17,11 @@ pub mod mask;
 pub mod merge;
 pub mod misc;
 pub mod plot;
+pub mod qtrim;
 pub mod range;
 pub mod remove;
 pub mod rename;
+pub mod report;
 pub mod reverse;
 pub mod search;
 pub mod select;
@@ -32,4 +36,5 @@ pub mod subfq;
 pub mod tail;
 pub mod top;
 pub mod trimfq;
+pub mod umi;
 pub mod view;
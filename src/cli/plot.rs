@@ -2,7 +2,7 @@ use crate::{errors::FqkitError, utils::file_reader};
 use anyhow::Result;
 use colored::Colorize;
 use log::error;
-use plotters::{prelude::*, style::Color};
+use plotters::{coord::Shift, prelude::*, style::Color};
 use std::collections::BTreeMap;
 use std::io::BufRead;
 use textplots::{Chart, ColorPlot, LabelBuilder, LabelFormat, Shape};
@@ -49,7 +49,6 @@ pub fn plot_line(
         error!("{}", FqkitError::InvalidFigureType);
         std::process::exit(1);
     }
-    let max_len = *data[0].iter().last().unwrap().0 as f32;
     if ylim < 0.0 {
         error!("invalid args ylim.");
         std::process::exit(1);
@@ -59,252 +58,157 @@ pub fn plot_line(
     } else {
         format!("{}.svg", prefix)
     };
-    if types == "png" {
-        let png = BitMapBackend::new(&name, (width as u32, height as u32)).into_drawing_area();
-        png.fill(&WHITE)?;
-
-        let mut charts = ChartBuilder::on(&png)
-            .margin(10)
-            .caption("Base distrbution plot", ("sans-serif", 40).into_font())
-            .x_label_area_size(40)
-            .y_label_area_size(40)
-            .build_cartesian_2d(0.1..max_len, -0.5f32..ylim)?;
-
-        charts
-            .configure_mesh()
-            .x_labels(20)
-            .x_desc("position")
-            .x_label_formatter(&|x| format!("{:.0}", x))
-            .y_labels(10)
-            .y_label_formatter(&|x| format!("{:.1}", x))
-            .y_desc("percent")
-            .draw()?;
-
-        let nt_a = data[0]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_t = data[1]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_g = data[2]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_c = data[3]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_n = data[4]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-
-        if show {
-            let reads_len = nt_a.len();
-            println!(
-                "read length: {}\t\t{}\t{}\t{}\t{}\t{}",
-                reads_len,
-                "A".truecolor(255, 0, 0).bold(),
-                "T".truecolor(0, 255, 0).bold(),
-                "G".truecolor(255, 255, 0).bold(),
-                "C".truecolor(0, 0, 255).bold(),
-                "N".truecolor(0, 255, 255).bold()
-            );
-            Chart::new_with_y_range(200, 80, 0.0, reads_len as f32, 0.0, ylim)
-                .linecolorplot(
-                    &Shape::Lines(nt_c.as_slice()),
-                    rgb::RGB { r: 0, g: 0, b: 255 },
-                ) // C blue
-                .linecolorplot(
-                    &Shape::Lines(nt_t.as_slice()),
-                    rgb::RGB { r: 0, g: 255, b: 0 },
-                ) // T green
-                .linecolorplot(
-                    &Shape::Lines(nt_a.as_slice()),
-                    rgb::RGB { r: 255, g: 0, b: 0 },
-                ) // A red
-                .linecolorplot(
-                    &Shape::Lines(nt_g.as_slice()),
-                    rgb::RGB {
-                        r: 255,
-                        g: 255,
-                        b: 0,
-                    },
-                ) // G yellow
-                .linecolorplot(
-                    &Shape::Lines(nt_n.as_slice()),
-                    rgb::RGB {
-                        r: 0,
-                        g: 255,
-                        b: 255,
-                    },
-                ) // N white
-                //.x_label_format(LabelFormat::Custom(Box::new( |_| { String::from("position")})))
-                .y_label_format(LabelFormat::Value)
-                .nice();
-        }
-
-        charts
-            .draw_series(LineSeries::new(nt_a, RED))
-            .unwrap()
-            .label("A")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
-
-        charts
-            .draw_series(LineSeries::new(nt_t, GREEN))
-            .unwrap()
-            .label("T")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
 
-        charts
-            .draw_series(LineSeries::new(nt_g, YELLOW))
-            .unwrap()
-            .label("G")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], YELLOW));
-
-        charts
-            .draw_series(LineSeries::new(nt_c, BLACK))
-            .unwrap()
-            .label("C")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
-
-        charts
-            .draw_series(LineSeries::new(nt_n, BLUE))
-            .unwrap()
-            .label("N")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+    if show {
+        let [nt_a, nt_t, nt_g, nt_c, nt_n] = base_series(&data);
+        let reads_len = nt_a.len();
+        println!(
+            "read length: {}\t\t{}\t{}\t{}\t{}\t{}",
+            reads_len,
+            "A".truecolor(255, 0, 0).bold(),
+            "T".truecolor(0, 255, 0).bold(),
+            "G".truecolor(255, 255, 0).bold(),
+            "C".truecolor(0, 0, 255).bold(),
+            "N".truecolor(0, 255, 255).bold()
+        );
+        Chart::new_with_y_range(200, 80, 0.0, reads_len as f32, 0.0, ylim)
+            .linecolorplot(
+                &Shape::Lines(nt_c.as_slice()),
+                rgb::RGB { r: 0, g: 0, b: 255 },
+            ) // C blue
+            .linecolorplot(
+                &Shape::Lines(nt_t.as_slice()),
+                rgb::RGB { r: 0, g: 255, b: 0 },
+            ) // T green
+            .linecolorplot(
+                &Shape::Lines(nt_a.as_slice()),
+                rgb::RGB { r: 255, g: 0, b: 0 },
+            ) // A red
+            .linecolorplot(
+                &Shape::Lines(nt_g.as_slice()),
+                rgb::RGB {
+                    r: 255,
+                    g: 255,
+                    b: 0,
+                },
+            ) // G yellow
+            .linecolorplot(
+                &Shape::Lines(nt_n.as_slice()),
+                rgb::RGB {
+                    r: 0,
+                    g: 255,
+                    b: 255,
+                },
+            ) // N white
+            //.x_label_format(LabelFormat::Custom(Box::new( |_| { String::from("position")})))
+            .y_label_format(LabelFormat::Value)
+            .nice();
+    }
 
-        charts
-            .configure_series_labels()
-            .background_style(WHITE.mix(0.9))
-            .border_style(BLACK)
-            .position(SeriesLabelPosition::UpperRight)
-            .draw()?;
+    if types == "png" {
+        let png = BitMapBackend::new(&name, (width as u32, height as u32)).into_drawing_area();
+        draw_base_line(&png, &data, ylim)?;
     } else {
         let svg = SVGBackend::new(&name, (width as u32, height as u32)).into_drawing_area();
-        svg.fill(&WHITE)?;
+        draw_base_line(&svg, &data, ylim)?;
+    }
 
-        let mut charts = ChartBuilder::on(&svg)
-            .margin(10)
-            .caption("Base distrbution plot", ("sans-serif", 40).into_font())
-            .x_label_area_size(40)
-            .y_label_area_size(40)
-            .build_cartesian_2d(0.1..max_len, -0.5f32..ylim)?;
+    Ok(())
+}
 
-        charts
-            .configure_mesh()
-            .x_labels(20)
-            .x_desc("position")
-            .x_label_formatter(&|x| format!("{:.0}", x))
-            .y_labels(10)
-            .y_label_formatter(&|x| format!("{:.1}", x))
-            .y_desc("percent")
-            .draw()?;
+// base line plot rendered into a svg string
+pub fn base_plot_svg(
+    data: &[BTreeMap<usize, f64>],
+    width: usize,
+    height: usize,
+    ylim: f32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut buf = String::new();
+    {
+        let svg =
+            SVGBackend::with_string(&mut buf, (width as u32, height as u32)).into_drawing_area();
+        draw_base_line(&svg, data, ylim)?;
+    }
+    Ok(buf)
+}
 
-        let nt_a = data[0]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_t = data[1]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_g = data[2]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_c = data[3]
+// points of base A T G C N rate in position
+fn base_series(data: &[BTreeMap<usize, f64>]) -> [Vec<(f32, f32)>; 5] {
+    [0, 1, 2, 3, 4].map(|i| {
+        data[i]
             .iter()
             .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-        let nt_n = data[4]
-            .iter()
-            .map(|(k, v)| (*k as f32, *v as f32))
-            .collect::<Vec<(f32, f32)>>();
-
-        if show {
-            let reads_len = nt_a.len();
-            println!(
-                "read length: {}\t\t{}\t{}\t{}\t{}\t{}",
-                reads_len,
-                "A".truecolor(255, 0, 0).bold(),
-                "T".truecolor(0, 255, 0).bold(),
-                "G".truecolor(255, 255, 0).bold(),
-                "C".truecolor(0, 0, 255).bold(),
-                "N".truecolor(0, 255, 255).bold()
-            );
-            Chart::new_with_y_range(200, 80, 0.0, reads_len as f32, 0.0, ylim)
-                .linecolorplot(
-                    &Shape::Lines(nt_c.as_slice()),
-                    rgb::RGB { r: 0, g: 0, b: 255 },
-                ) // C blue
-                .linecolorplot(
-                    &Shape::Lines(nt_t.as_slice()),
-                    rgb::RGB { r: 0, g: 255, b: 0 },
-                ) // T green
-                .linecolorplot(
-                    &Shape::Lines(nt_a.as_slice()),
-                    rgb::RGB { r: 255, g: 0, b: 0 },
-                ) // A red
-                .linecolorplot(
-                    &Shape::Lines(nt_g.as_slice()),
-                    rgb::RGB {
-                        r: 255,
-                        g: 255,
-                        b: 0,
-                    },
-                ) // G yellow
-                .linecolorplot(
-                    &Shape::Lines(nt_n.as_slice()),
-                    rgb::RGB {
-                        r: 0,
-                        g: 255,
-                        b: 255,
-                    },
-                ) // N white
-                .y_label_format(LabelFormat::Value)
-                .nice();
-        }
-
-        charts
-            .draw_series(LineSeries::new(nt_a, RED))
-            .unwrap()
-            .label("A")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
-
-        charts
-            .draw_series(LineSeries::new(nt_t, GREEN))
-            .unwrap()
-            .label("T")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
-
-        charts
-            .draw_series(LineSeries::new(nt_g, YELLOW))
-            .unwrap()
-            .label("G")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], YELLOW));
-
-        charts
-            .draw_series(LineSeries::new(nt_c, BLACK))
-            .unwrap()
-            .label("C")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
-
-        charts
-            .draw_series(LineSeries::new(nt_n, BLUE))
-            .unwrap()
-            .label("N")
-            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+            .collect::<Vec<(f32, f32)>>()
+    })
+}
 
-        charts
-            .configure_series_labels()
-            .background_style(WHITE.mix(0.9))
-            .border_style(BLACK)
-            .position(SeriesLabelPosition::UpperRight)
-            .draw()?;
-    }
+fn draw_base_line<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &[BTreeMap<usize, f64>],
+    ylim: f32,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let max_len = *data[0].iter().last().unwrap().0 as f32;
+    root.fill(&WHITE)?;
+
+    let mut charts = ChartBuilder::on(root)
+        .margin(10)
+        .caption("Base distrbution plot", ("sans-serif", 40).into_font())
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0.1..max_len, -0.5f32..ylim)?;
+
+    charts
+        .configure_mesh()
+        .x_labels(20)
+        .x_desc("position")
+        .x_label_formatter(&|x| format!("{:.0}", x))
+        .y_labels(10)
+        .y_label_formatter(&|x| format!("{:.1}", x))
+        .y_desc("percent")
+        .draw()?;
+
+    let [nt_a, nt_t, nt_g, nt_c, nt_n] = base_series(data);
+
+    charts
+        .draw_series(LineSeries::new(nt_a, RED))
+        .unwrap()
+        .label("A")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+
+    charts
+        .draw_series(LineSeries::new(nt_t, GREEN))
+        .unwrap()
+        .label("T")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
+
+    charts
+        .draw_series(LineSeries::new(nt_g, YELLOW))
+        .unwrap()
+        .label("G")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], YELLOW));
+
+    charts
+        .draw_series(LineSeries::new(nt_c, BLACK))
+        .unwrap()
+        .label("C")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
+
+    charts
+        .draw_series(LineSeries::new(nt_n, BLUE))
+        .unwrap()
+        .label("N")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+
+    charts
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.9))
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperRight)
+        .draw()?;
+    root.present()?;
 
     Ok(())
 }
@@ -0,0 +1,424 @@
+use super::misc::write_record;
+use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
+use log::{error, info};
+use paraseq::{
+    fastq,
+    fastx::Record,
+    parallel::{
+        PairedParallelProcessor, PairedParallelReader, ParallelProcessor, ParallelReader,
+        ProcessError,
+    },
+};
+use parking_lot::Mutex;
+use std::{io::Write, sync::Arc};
+
+type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+// 3' homopolymer tail, eg. polyG of two-colour chemistry
+#[derive(Clone, Debug)]
+pub struct PolyTail {
+    bases: Vec<u8>,
+    min_len: usize,
+    max_mismatch: usize,
+}
+
+impl PolyTail {
+    // bases in A/T/G/C/N, X for a run of any base
+    pub fn new(bases: &str, min_len: usize, max_mismatch: usize) -> Self {
+        let bases = bases.to_ascii_uppercase().into_bytes();
+        if bases.is_empty() || bases.iter().any(|b| !b"ATGCNX".contains(b)) {
+            error!("invalid poly tail bases, only A/T/G/C/N/X are allowed");
+            std::process::exit(1);
+        }
+        Self {
+            bases,
+            min_len: min_len.max(1),
+            max_mismatch,
+        }
+    }
+
+    // length of the poly tail on the 3' end, 0 if no tail reaches the min length
+    pub fn tail_len(&self, seq: &[u8]) -> usize {
+        let mut end = seq.len();
+        for base in self.bases.iter() {
+            let base = if *base == b'X' {
+                match seq[..end].last() {
+                    Some(b) => b.to_ascii_uppercase(),
+                    None => break,
+                }
+            } else {
+                *base
+            };
+            end -= self.run_len(&seq[..end], base);
+        }
+        seq.len() - end
+    }
+
+    // a tail run must end with the polymer base at its 5' side, a mismatch is only bridged
+    // when at least two matching bases follow it, so a lone base after a mismatch is kept
+    fn run_len(&self, seq: &[u8], base: u8) -> usize {
+        let (mut mismatch, mut tail, mut matched) = (0, 0, 0);
+        let (mut seen, mut run) = (0, 0);
+        for (i, b) in seq.iter().rev().enumerate() {
+            if b.to_ascii_uppercase() == base {
+                seen += 1;
+                run += 1;
+                if mismatch == 0 || run >= 2 {
+                    tail = i + 1;
+                    matched = seen;
+                }
+            } else {
+                mismatch += 1;
+                run = 0;
+                if mismatch > self.max_mismatch {
+                    break;
+                }
+            }
+        }
+        if matched >= self.min_len { tail } else { 0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct QualTrim {
+    pub leading: u8,
+    pub trailing: u8,
+    pub window: usize,
+    pub window_qual: u8,
+    pub bwa_qual: u8,
+    pub poly: Option<PolyTail>,
+    pub phred: u8,
+}
+
+impl QualTrim {
+    // kept range [start, end) of a read after all enabled trimming steps
+    pub fn trim_range(&self, seq: &[u8], qual: &[u8]) -> (usize, usize) {
+        let qual = qual
+            .iter()
+            .map(|q| q.saturating_sub(self.phred))
+            .collect::<Vec<u8>>();
+        let (mut start, mut end) = (0, qual.len());
+
+        // poly tail is removed before quality trimming
+        if let Some(poly) = &self.poly {
+            end -= poly.tail_len(seq);
+        }
+
+        // LEADING: cut bases off the start while below the threshold
+        while start < end && qual[start] < self.leading {
+            start += 1;
+        }
+        // TRAILING: cut bases off the end while below the threshold
+        while end > start && qual[end - 1] < self.trailing {
+            end -= 1;
+        }
+        if self.window > 0 {
+            end = start + sliding_window(&qual[start..end], self.window, self.window_qual);
+        }
+        if self.bwa_qual > 0 {
+            end = start + bwa_trim(&qual[start..end], self.bwa_qual);
+        }
+        (start, end)
+    }
+}
+
+// trimmomatic SLIDINGWINDOW, scan from 5' end and cut once the average quality
+// in the window falls below the threshold, keep the good bases in that window
+fn sliding_window(qual: &[u8], window: usize, window_qual: u8) -> usize {
+    let window = window.min(qual.len());
+    if window == 0 {
+        return 0;
+    }
+    let required = window * window_qual as usize;
+    let mut total = qual[..window].iter().map(|q| *q as usize).sum::<usize>();
+    for i in 0..=qual.len() - window {
+        if i > 0 {
+            total = total + qual[i + window - 1] as usize - qual[i - 1] as usize;
+        }
+        if total < required {
+            return qual[i..i + window]
+                .iter()
+                .position(|q| *q < window_qual)
+                .map_or(i + window, |x| i + x);
+        }
+    }
+    qual.len()
+}
+
+// BWA -q, cut the 3' end at the position maximizing sum(threshold - qual)
+fn bwa_trim(qual: &[u8], cutoff: u8) -> usize {
+    let (mut sum, mut max_sum, mut max_i) = (0i64, 0i64, qual.len());
+    for i in (0..qual.len()).rev() {
+        sum += cutoff as i64 - qual[i] as i64;
+        if sum < 0 {
+            break;
+        }
+        if sum > max_sum {
+            max_sum = sum;
+            max_i = i;
+        }
+    }
+    max_i
+}
+
+#[derive(Clone)]
+struct Trimmer {
+    trim: QualTrim,
+    length: usize,
+    buffer1: Vec<u8>,
+    buffer2: Vec<u8>,
+    unpaired_buffer: Vec<u8>,
+    // kept reads(pairs), dropped reads(pairs), orphan read1, orphan read2, trimmed bases
+    count: [usize; 5],
+    total_count: Arc<Mutex<[usize; 5]>>,
+    writer1: Writer,
+    writer2: Option<Writer>,
+    unpaired_writer: Option<Writer>,
+}
+
+impl Trimmer {
+    // trimmed sequence and quality, and whether it is long enough to keep
+    fn trim_record<'a, Rf: Record>(&mut self, record: &'a Rf) -> (&'a [u8], &'a [u8], bool) {
+        let qual = record.qual().unwrap();
+        let (start, end) = self.trim.trim_range(record.seq(), qual);
+        self.count[4] += qual.len() - (end - start);
+        (
+            &record.seq()[start..end],
+            &qual[start..end],
+            end - start >= self.length,
+        )
+    }
+
+    fn flush_batch(&mut self) -> std::io::Result<()> {
+        let mut total_count = self.total_count.lock();
+        for (t, c) in total_count.iter_mut().zip(self.count.iter()) {
+            *t += *c;
+        }
+        drop(total_count);
+
+        let mut writer1 = self.writer1.lock();
+        writer1.write_all(&self.buffer1)?;
+        writer1.flush()?;
+        if let Some(writer2) = &self.writer2 {
+            let mut writer2 = writer2.lock();
+            writer2.write_all(&self.buffer2)?;
+            writer2.flush()?;
+        }
+        if let Some(unpaired_writer) = &self.unpaired_writer {
+            let mut writer = unpaired_writer.lock();
+            writer.write_all(&self.unpaired_buffer)?;
+            writer.flush()?;
+        }
+
+        // reset for next batch
+        self.buffer1.clear();
+        self.buffer2.clear();
+        self.unpaired_buffer.clear();
+        self.count = [0; 5];
+        Ok(())
+    }
+}
+
+impl ParallelProcessor for Trimmer {
+    fn process_record<Rf: Record>(&mut self, record: Rf) -> Result<(), ProcessError> {
+        let (seq, qual, ok) = self.trim_record(&record);
+        if ok {
+            write_record(&mut self.buffer1, record.id(), seq, qual)?;
+            self.count[0] += 1;
+        } else {
+            self.count[1] += 1;
+        }
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+impl PairedParallelProcessor for Trimmer {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        let (seq1, qual1, ok1) = self.trim_record(&rec1);
+        let (seq2, qual2, ok2) = self.trim_record(&rec2);
+        match (ok1, ok2) {
+            (true, true) => {
+                write_record(&mut self.buffer1, rec1.id(), seq1, qual1)?;
+                write_record(&mut self.buffer2, rec2.id(), seq2, qual2)?;
+                self.count[0] += 1;
+            }
+            (true, false) if self.unpaired_writer.is_some() => {
+                write_record(&mut self.unpaired_buffer, rec1.id(), seq1, qual1)?;
+                self.count[2] += 1;
+            }
+            (false, true) if self.unpaired_writer.is_some() => {
+                write_record(&mut self.unpaired_buffer, rec2.id(), seq2, qual2)?;
+                self.count[3] += 1;
+            }
+            _ => self.count[1] += 1,
+        }
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn qual_trim_fastq(
+    read1: &String,
+    read2: Option<&String>,
+    trim: QualTrim,
+    length: usize,
+    ncpu: usize,
+    unpaired: Option<&String>,
+    out1: Option<&String>,
+    out2: Option<&String>,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), FqkitError> {
+    if ![33u8, 64u8].contains(&trim.phred) {
+        error!("{}", FqkitError::InvalidPhredValue);
+        std::process::exit(1);
+    }
+    if read2.is_some() && (out1.is_none() || out2.is_none()) {
+        error!("both -f/--out1 and -r/--out2 are required for pair end reads");
+        std::process::exit(1);
+    }
+    if trim.window > 0 {
+        info!(
+            "sliding window: {}, required quality: {}",
+            trim.window, trim.window_qual
+        );
+    }
+    if let Some(poly) = &trim.poly {
+        info!(
+            "poly tail: {}, min length: {}, max mismatch: {}",
+            String::from_utf8_lossy(&poly.bases),
+            poly.min_len,
+            poly.max_mismatch
+        );
+    }
+
+    let fq_reader1 = file_reader(Some(read1)).map(fastq::Reader::new)?;
+    let writer1 = file_writer(out1, compression_level, stdout_type)?;
+    let writer2 = if read2.is_some() {
+        Some(file_writer(out2, compression_level, stdout_type)?)
+    } else {
+        None
+    };
+    let unpaired_writer = if let Some(unpaired) = unpaired {
+        Some(file_writer(Some(unpaired), compression_level, stdout_type)?)
+    } else {
+        None
+    };
+
+    let trimmer = Trimmer {
+        trim,
+        length,
+        buffer1: vec![],
+        buffer2: vec![],
+        unpaired_buffer: vec![],
+        count: [0; 5],
+        total_count: Arc::new(Mutex::new([0; 5])),
+        writer1: Arc::new(Mutex::new(writer1)),
+        writer2: writer2.map(|w| Arc::new(Mutex::new(w))),
+        unpaired_writer: unpaired_writer.map(|w| Arc::new(Mutex::new(w))),
+    };
+
+    if let Some(read2) = read2 {
+        let fq_reader2 = file_reader(Some(read2)).map(fastq::Reader::new)?;
+        fq_reader1.process_parallel_paired(fq_reader2, trimmer.clone(), ncpu)?;
+
+        let count = trimmer.total_count.lock();
+        info!("total kept pe reads number (r1+r2): {}", count[0] * 2);
+        info!("total dropped pe reads number (r1+r2): {}", count[1] * 2);
+        info!(
+            "orphan read1 number: {}, orphan read2 number: {}",
+            count[2], count[3]
+        );
+        info!("total trimmed bases: {}", count[4]);
+    } else {
+        fq_reader1.process_parallel(trimmer.clone(), ncpu)?;
+
+        let count = trimmer.total_count.lock();
+        info!("total kept reads number: {}", count[0]);
+        info!("total dropped reads number: {}", count[1]);
+        info!("total trimmed bases: {}", count[4]);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trimmer(
+        leading: u8,
+        trailing: u8,
+        window: usize,
+        window_qual: u8,
+        bwa_qual: u8,
+    ) -> QualTrim {
+        QualTrim {
+            leading,
+            trailing,
+            window,
+            window_qual,
+            bwa_qual,
+            poly: None,
+            phred: 33,
+        }
+    }
+
+    fn trim_qual(trim: &QualTrim, qual: &[u8]) -> (usize, usize) {
+        trim.trim_range(&vec![b'A'; qual.len()], qual)
+    }
+
+    fn qual(q: &[u8]) -> Vec<u8> {
+        q.iter().map(|x| x + 33).collect()
+    }
+
+    #[test]
+    fn leading_trailing() {
+        let q = qual(&[2, 3, 30, 30, 30, 10, 2]);
+        assert_eq!(trim_qual(&trimmer(3, 3, 0, 0, 0), &q), (1, 6));
+        assert_eq!(trim_qual(&trimmer(20, 20, 0, 0, 0), &q), (2, 5));
+        assert_eq!(trim_qual(&trimmer(40, 0, 0, 0, 0), &q), (7, 7));
+    }
+
+    #[test]
+    fn window() {
+        let q = qual(&[30, 30, 30, 30, 30, 30, 10, 10, 30, 10]);
+        assert_eq!(trim_qual(&trimmer(0, 0, 4, 20, 0), &q), (0, 6));
+        assert_eq!(trim_qual(&trimmer(0, 0, 4, 5, 0), &q), (0, 10));
+        // window larger than the read
+        assert_eq!(trim_qual(&trimmer(0, 0, 20, 25, 0), &q), (0, 6));
+    }
+
+    #[test]
+    fn bwa() {
+        // same example as in cutadapt documentation
+        let q = qual(&[42, 40, 26, 27, 8, 7, 11, 4, 2, 3]);
+        assert_eq!(trim_qual(&trimmer(0, 0, 0, 0, 10), &q), (0, 4));
+        assert_eq!(trim_qual(&trimmer(0, 0, 0, 0, 10), &qual(&[])), (0, 0));
+    }
+
+    #[test]
+    fn poly_tail() {
+        let poly = PolyTail::new("G", 5, 1);
+        // the G before the mismatched T is not followed by more Gs, it is not part of the tail
+        assert_eq!(poly.tail_len(b"ACGTACGTGGGGGG"), 6);
+        assert_eq!(poly.tail_len(b"ACGTACGGTGGGGGG"), 9);
+        assert_eq!(PolyTail::new("G", 5, 0).tail_len(b"ACGTACGTGGGGGG"), 6);
+        assert_eq!(poly.tail_len(b"ACGTACGTGGGAGGGGG"), 9);
+        assert_eq!(poly.tail_len(b"ACGTACGTACGGGG"), 0);
+        assert_eq!(PolyTail::new("G", 6, 1).tail_len(b"ACGTACGTGGTGGAGGG"), 0);
+        assert_eq!(PolyTail::new("GA", 5, 0).tail_len(b"ACGTCAAAAAGGGGG"), 10);
+        assert_eq!(PolyTail::new("X", 5, 0).tail_len(b"ACGTCGTTTTTT"), 6);
+        assert_eq!(PolyTail::new("X", 5, 0).tail_len(b""), 0);
+    }
+}
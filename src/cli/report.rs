@@ -0,0 +1,315 @@
+use super::{
+    filter::Complexity,
+    gcplot::{Gchash, gc_plot_svg},
+    plot::base_plot_svg,
+    stats::{Info, length_hist, summary_items},
+};
+use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
+use anyhow::{Error, anyhow};
+use chrono::Local;
+use log::{error, info};
+use paraseq::{
+    fastq,
+    fastx::Record,
+    parallel::{ParallelProcessor, ParallelReader, ProcessError},
+};
+use parking_lot::Mutex;
+use plotters::{coord::Shift, prelude::*};
+use std::{fmt::Write as _, io::Write, sync::Arc};
+
+#[derive(Clone)]
+struct Report {
+    stat: Info,
+    gc: Gchash,
+    method: Complexity,
+    // read count of each complexity score 0~100
+    complexity: Vec<usize>,
+    total_complexity: Arc<Mutex<Vec<usize>>>,
+}
+
+impl ParallelProcessor for Report {
+    fn process_record<Rf: Record>(&mut self, record: Rf) -> Result<(), ProcessError> {
+        self.gc.add_seq(record.seq());
+        self.complexity[self.method.score(record.seq()).round() as usize] += 1;
+        self.stat.process_record(record)?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.stat.on_batch_complete()?;
+        self.gc.on_batch_complete()?;
+        let mut total_complexity = self.total_complexity.lock();
+        for (t, c) in total_complexity.iter_mut().zip(self.complexity.iter()) {
+            *t += *c;
+        }
+        self.complexity = vec![0; 101];
+        Ok(())
+    }
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em auto;max-width:1000px;color:#333}\
+h1{border-bottom:2px solid #4a90d9}h2{color:#4a90d9;margin-top:1.5em}\
+table{border-collapse:collapse;margin:1em 0}td,th{border:1px solid #ccc;padding:4px 10px;text-align:left}\
+th{background:#eef3fa}tr:nth-child(even) td{background:#fafafa}svg{max-width:100%;height:auto}";
+
+#[allow(clippy::too_many_arguments)]
+pub fn report_fq(
+    input: Option<&String>,
+    out: &String,
+    title: &str,
+    phred: u8,
+    method: Complexity,
+    width: usize,
+    height: usize,
+    ncpu: usize,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), Error> {
+    if ![33u8, 64u8].contains(&phred) {
+        error!("{}", FqkitError::InvalidPhredValue);
+        std::process::exit(1);
+    }
+
+    let fq_reader = file_reader(input).map(fastq::Reader::new)?;
+    let mut report = Report {
+        stat: Info::new(phred, 0, false),
+        gc: Gchash::new(),
+        method,
+        complexity: vec![0; 101],
+        total_complexity: Arc::new(Mutex::new(vec![0; 101])),
+    };
+    fq_reader.process_parallel(report.clone(), ncpu)?;
+    let name = input.map_or("stdin", |x| x.as_str());
+    if report.stat.read_count() == 0 {
+        error!("{}", FqkitError::EmptyFile(name.to_string()));
+        std::process::exit(1);
+    }
+    report.stat.calc();
+
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(
+        html,
+        "<title>{}</title>\n<style>{}</style>",
+        escape(title),
+        STYLE
+    )?;
+    writeln!(html, "</head>\n<body>\n<h1>{}</h1>", escape(title))?;
+    writeln!(
+        html,
+        "<p>input: {}<br>phred: {}<br>created by fqkit v{} at {}</p>",
+        escape(name),
+        phred,
+        env!("CARGO_PKG_VERSION"),
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    )?;
+
+    // summary table, same items as stats summary file
+    writeln!(html, "<h2>Summary</h2>\n<table>")?;
+    writeln!(html, "<tr><th>item</th><th>value</th><th>rate(%)</th></tr>")?;
+    for (item, value, rate) in summary_items(&report.stat) {
+        if item.is_empty() {
+            continue;
+        }
+        let rate = rate.map_or(String::new(), |x| format!("{:.2}", x * 100.0));
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(item),
+            value,
+            rate
+        )?;
+    }
+    writeln!(html, "</table>")?;
+
+    // base content and quality in read position
+    let cycle = report.stat.cycle_base_rate();
+    let base_max = cycle
+        .iter()
+        .flat_map(|x| x.values())
+        .fold(0.0f64, |a, b| a.max(*b));
+    let base_svg = base_plot_svg(&cycle, width, height, (base_max * 1.1).min(100.0) as f32)
+        .map_err(|e| anyhow!("{}", e))?;
+    writeln!(html, "<h2>Base content per cycle</h2>\n{}", base_svg)?;
+
+    let cycle_qual = report
+        .stat
+        .cycle_mean_qual()
+        .iter()
+        .enumerate()
+        .map(|(i, q)| (i as f64 + 1.0, *q))
+        .collect::<Vec<(f64, f64)>>();
+    let qual_svg = line_plot_svg(
+        &cycle_qual,
+        "Mean quality per cycle",
+        "position",
+        "quality",
+        width,
+        height,
+    )?;
+    writeln!(html, "<h2>Quality per cycle</h2>\n{}", qual_svg)?;
+
+    // per read mean quality distribution
+    let num_read = report.stat.read_count() as f64;
+    let read_qual = report.stat.read_qual_count();
+    let max_qual = read_qual.keys().max().copied().unwrap_or(0);
+    let read_qual = (0..=max_qual)
+        .map(|q| {
+            let num = *read_qual.get(&q).unwrap_or(&0) as f64;
+            (q as f64, num / num_read * 100.0)
+        })
+        .collect::<Vec<(f64, f64)>>();
+    let read_qual_svg = line_plot_svg(
+        &read_qual,
+        "Per read mean quality",
+        "mean quality",
+        "percent",
+        width,
+        height,
+    )?;
+    writeln!(
+        html,
+        "<h2>Read quality distribution</h2>\n{}",
+        read_qual_svg
+    )?;
+
+    // GC content distribution
+    let gc = report.gc.gc_rate();
+    let gc_max = gc.iter().fold(0.0f32, |a, b| a.max(*b));
+    let gc_svg = gc_plot_svg(&gc, width, height, (gc_max * 1.1).min(100.0))?;
+    writeln!(html, "<h2>GC content distribution</h2>\n{}", gc_svg)?;
+
+    // read complexity distribution
+    let complexity = report
+        .total_complexity
+        .lock()
+        .iter()
+        .enumerate()
+        .map(|(i, num)| (i as f64, *num as f64 / num_read * 100.0))
+        .collect::<Vec<(f64, f64)>>();
+    let complexity_svg = line_plot_svg(
+        &complexity,
+        &format!("Read complexity distribution ({:?})", method),
+        "complexity",
+        "percent",
+        width,
+        height,
+    )?;
+    writeln!(html, "<h2>Complexity distribution</h2>\n{}", complexity_svg)?;
+
+    // read length distribution
+    let len_count = report.stat.length_count();
+    let mut lens = len_count.iter().collect::<Vec<(&usize, &usize)>>();
+    lens.sort_unstable();
+    let len_points = lens
+        .iter()
+        .map(|(len, num)| (**len as f64, **num as f64 / num_read * 100.0))
+        .collect::<Vec<(f64, f64)>>();
+    let len_svg = line_plot_svg(
+        &len_points,
+        "Read length distribution",
+        "length",
+        "percent",
+        width,
+        height,
+    )?;
+    writeln!(html, "<h2>Length distribution</h2>\n{}\n<table>", len_svg)?;
+    writeln!(
+        html,
+        "<tr><th>length</th><th>count</th><th>rate(%)</th></tr>"
+    )?;
+    for (start, end, count) in length_hist(&len_count, 20) {
+        let range = if start == end {
+            format!("{}", start)
+        } else {
+            format!("{}-{}", start, end)
+        };
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{:.2}</td></tr>",
+            range,
+            count,
+            count as f64 / num_read * 100.0
+        )?;
+    }
+    writeln!(html, "</table>\n</body>\n</html>")?;
+
+    info!("output html report: {}", out);
+    let mut fo = file_writer(Some(out), compression_level, stdout_type)?;
+    fo.write_all(html.as_bytes())?;
+    fo.flush()?;
+
+    Ok(())
+}
+
+// simple line plot rendered into a svg string
+fn line_plot_svg(
+    data: &[(f64, f64)],
+    caption: &str,
+    x_desc: &str,
+    y_desc: &str,
+    width: usize,
+    height: usize,
+) -> Result<String, Error> {
+    let mut buf = String::new();
+    {
+        let svg =
+            SVGBackend::with_string(&mut buf, (width as u32, height as u32)).into_drawing_area();
+        draw_line(&svg, data, caption, x_desc, y_desc)?;
+    }
+    Ok(buf)
+}
+
+fn draw_line<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &[(f64, f64)],
+    caption: &str,
+    x_desc: &str,
+    y_desc: &str,
+) -> Result<(), Error>
+where
+    DB::ErrorType: 'static,
+{
+    let x_min = data.iter().map(|x| x.0).fold(f64::MAX, f64::min);
+    let x_max = data.iter().map(|x| x.0).fold(f64::MIN, f64::max);
+    let y_max = data.iter().map(|x| x.1).fold(0.0, f64::max);
+    // keep a visible range for single point data
+    let x_max = if x_max > x_min { x_max } else { x_min + 1.0 };
+    let y_max = if y_max > 0.0 { y_max * 1.1 } else { 1.0 };
+
+    root.fill(&WHITE)?;
+    let mut charts = ChartBuilder::on(root)
+        .margin(10)
+        .caption(caption, ("sans-serif", 30).into_font())
+        .x_label_area_size(40)
+        .y_label_area_size(50)
+        .build_cartesian_2d(x_min..x_max, 0.0..y_max)?;
+
+    charts
+        .configure_mesh()
+        .x_labels(20)
+        .x_desc(x_desc)
+        .x_label_formatter(&|x| format!("{:.0}", x))
+        .y_labels(10)
+        .y_label_formatter(&|x| format!("{:.1}", x))
+        .y_desc(y_desc)
+        .draw()?;
+
+    charts.draw_series(
+        AreaSeries::new(data.iter().copied(), 0.0, BLUE.mix(0.2)).border_style(BLUE),
+    )?;
+    root.present()?;
+
+    Ok(())
+}
+
+// escape text for html
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,17 +1,31 @@
+use super::{cutadapter::insert_by_overlap, join::find_overlap, misc::reverse_complement};
 use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
-use log::{error, info};
+use log::{error, info, warn};
 use paraseq::{
     fastq,
     fastx::Record,
-    parallel::{ParallelProcessor, ParallelReader, ProcessError},
+    parallel::{
+        PairedParallelProcessor, PairedParallelReader, ParallelProcessor, ParallelReader,
+        ProcessError,
+    },
 };
 use parking_lot::Mutex;
-use std::sync::Arc;
-use std::{collections::HashMap, vec};
+use plotters::{coord::Shift, prelude::*};
+use rayon::prelude::*;
+use serde_json::{Map, Value, json};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufRead, Write},
+    path::Path,
+    sync::Arc,
+    vec,
+};
+
+type TileQual = HashMap<(usize, usize), (usize, usize)>;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone)]
-struct Info {
+pub struct Info {
     num_a: usize,
     total_num_a: Arc<Mutex<usize>>,
     rate_a: f64,
@@ -51,17 +65,27 @@ struct Info {
     max_len: usize,
     total_max_len: Arc<Mutex<usize>>,
     min_len: Option<usize>,
-    total_min_len: Arc<Mutex<usize>>,
+    total_min_len: Arc<Mutex<Option<usize>>>,
+    // read length => read count
+    len_count: HashMap<usize, usize>,
+    total_len_count: Arc<Mutex<HashMap<usize, usize>>>,
 
     phred: u8,
     max_qva: u8,
     total_max_qva: Arc<Mutex<u8>>,
     each: HashMap<usize, Vec<usize>>,
     total_each: Arc<Mutex<HashMap<usize, Vec<usize>>>>,
+    // per-read mean quality => read count
+    read_qual: HashMap<usize, usize>,
+    total_read_qual: Arc<Mutex<HashMap<usize, usize>>>,
+    // (lane, tile) => (quality sum, base count)
+    tile: bool,
+    tile_qual: TileQual,
+    total_tile_qual: Arc<Mutex<TileQual>>,
 }
 
 impl Info {
-    fn new(phred: u8, max_qva: u8) -> Self {
+    pub fn new(phred: u8, max_qva: u8, tile: bool) -> Self {
         Info {
             num_a: 0,
             total_num_a: Arc::new(Mutex::new(0)),
@@ -102,15 +126,22 @@ impl Info {
             max_len: 0,
             total_max_len: Arc::new(Mutex::new(0)),
             min_len: None,
-            total_min_len: Arc::new(Mutex::new(0)),
+            total_min_len: Arc::new(Mutex::new(None)),
+            len_count: HashMap::new(),
+            total_len_count: Arc::new(Mutex::new(HashMap::new())),
             phred,
             max_qva,
             total_max_qva: Arc::new(Mutex::new(0)),
             each: HashMap::new(),
             total_each: Arc::new(Mutex::new(HashMap::new())),
+            read_qual: HashMap::new(),
+            total_read_qual: Arc::new(Mutex::new(HashMap::new())),
+            tile,
+            tile_qual: HashMap::new(),
+            total_tile_qual: Arc::new(Mutex::new(HashMap::new())),
         }
     }
-    fn calc(&mut self) {
+    pub fn calc(&mut self) {
         let num_base = *self.total_num_base.lock();
         let num_read = *self.total_num_read.lock();
         self.ave_len = num_base as f64 / num_read as f64;
@@ -127,6 +158,48 @@ impl Info {
         self.rate_q20 = *self.total_num_q20.lock() as f64 / num_base as f64;
         self.rate_q30 = *self.total_num_q30.lock() as f64 / num_base as f64;
     }
+
+    pub fn read_count(&self) -> usize {
+        *self.total_num_read.lock()
+    }
+
+    pub fn length_count(&self) -> HashMap<usize, usize> {
+        self.total_len_count.lock().clone()
+    }
+
+    pub fn read_qual_count(&self) -> HashMap<usize, usize> {
+        self.total_read_qual.lock().clone()
+    }
+
+    // base A T G C N rate(%) in each cycle, same layout as plot::cycle_data
+    pub fn cycle_base_rate(&self) -> Vec<BTreeMap<usize, f64>> {
+        let mut cyc = vec![BTreeMap::new(); 5];
+        let each = self.total_each.lock();
+        for (pos, data) in each.iter() {
+            let sum_each = data.iter().take(5).sum::<usize>() as f64;
+            for (i, base) in cyc.iter_mut().enumerate() {
+                let rate = (data[i] as f64 / sum_each * 10000.0).round() / 100.0;
+                base.insert(pos + 1, rate);
+            }
+        }
+        cyc
+    }
+
+    // mean base quality in each cycle
+    pub fn cycle_mean_qual(&self) -> Vec<f64> {
+        let each = self.total_each.lock();
+        (0..each.len())
+            .map(|pos| {
+                let data = &each[&pos];
+                let (sum, num) = data
+                    .iter()
+                    .skip(5)
+                    .enumerate()
+                    .fold((0, 0), |(s, n), (q, c)| (s + q * c, n + c));
+                sum as f64 / num as f64
+            })
+            .collect()
+    }
 }
 
 impl ParallelProcessor for Info {
@@ -134,7 +207,7 @@ impl ParallelProcessor for Info {
         self.num_read += 1;
         self.num_base += record.seq().len();
 
-        let this_q = *record.qual().unwrap().iter().max().unwrap() - self.phred;
+        let this_q = *record.qual().unwrap().iter().max().unwrap_or(&self.phred) - self.phred;
         if this_q > self.max_qva {
             self.max_qva = this_q;
         }
@@ -142,13 +215,25 @@ impl ParallelProcessor for Info {
         if len > self.max_len {
             self.max_len = len;
         }
-        match self.min_len {
-            Some(v) => {
-                if v >= len {
-                    self.min_len = Some(len)
-                }
+        self.min_len = Some(self.min_len.map_or(len, |v| v.min(len)));
+        *self.len_count.entry(len).or_insert(0) += 1;
+
+        let qual_sum = record
+            .qual()
+            .unwrap()
+            .iter()
+            .map(|q| (q - self.phred) as usize)
+            .sum::<usize>();
+        if len > 0 {
+            let mean_qual = (qual_sum as f64 / len as f64).round() as usize;
+            *self.read_qual.entry(mean_qual).or_insert(0) += 1;
+        }
+        if self.tile {
+            if let Some(key) = illumina_lane_tile(record.id()) {
+                let e = self.tile_qual.entry(key).or_insert((0, 0));
+                e.0 += qual_sum;
+                e.1 += len;
             }
-            None => self.min_len = Some(len),
         }
 
         for &base in record.seq() {
@@ -236,7 +321,6 @@ impl ParallelProcessor for Info {
     }
 
     fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
-
         *self.total_num_a.lock() += self.num_a;
         *self.total_num_t.lock() += self.num_t;
         *self.total_num_g.lock() += self.num_g;
@@ -250,9 +334,19 @@ impl ParallelProcessor for Info {
         *self.total_num_q20.lock() += self.num_q20;
         *self.total_num_q30.lock() += self.num_q30;
 
-        *self.total_max_qva.lock() = self.max_qva;
-        *self.total_max_len.lock() = self.max_len;
-        *self.total_min_len.lock() = self.min_len.unwrap_or(0);
+        // keep the global extremes, a batch only knows its own reads
+        let mut total_max_qva = self.total_max_qva.lock();
+        *total_max_qva = (*total_max_qva).max(self.max_qva);
+        let mut total_max_len = self.total_max_len.lock();
+        *total_max_len = (*total_max_len).max(self.max_len);
+        if let Some(min_len) = self.min_len {
+            let mut total_min_len = self.total_min_len.lock();
+            *total_min_len = Some(total_min_len.map_or(min_len, |v| v.min(min_len)));
+        }
+        let mut total_len_count = self.total_len_count.lock();
+        for (k, v) in self.len_count.iter() {
+            *total_len_count.entry(*k).or_insert(0) += *v;
+        }
 
         let mut total_each = self.total_each.lock();
         for (pos, v) in self.each.iter() {
@@ -270,6 +364,17 @@ impl ParallelProcessor for Info {
             }
         }
 
+        let mut total_read_qual = self.total_read_qual.lock();
+        for (k, v) in self.read_qual.iter() {
+            *total_read_qual.entry(*k).or_insert(0) += *v;
+        }
+        let mut total_tile_qual = self.total_tile_qual.lock();
+        for (k, v) in self.tile_qual.iter() {
+            let e = total_tile_qual.entry(*k).or_insert((0, 0));
+            e.0 += v.0;
+            e.1 += v.1;
+        }
+
         // reset for next batch
         self.num_a = 0;
         self.num_t = 0;
@@ -285,15 +390,113 @@ impl ParallelProcessor for Info {
         self.num_q30 = 0;
         self.max_len = 0;
         self.min_len = None;
+        self.len_count.clear();
         self.each = HashMap::new();
+        self.read_qual.clear();
+        self.tile_qual.clear();
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct PairInfo {
+    read1: Info,
+    read2: Info,
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+    // insert size => pair count
+    insert: HashMap<usize, usize>,
+    total_insert: Arc<Mutex<HashMap<usize, usize>>>,
+}
+
+impl PairInfo {
+    fn new(phred: u8, tile: bool, min_overlap_len: usize, max_mismatch_rate: f64) -> Self {
+        PairInfo {
+            read1: Info::new(phred, 0, tile),
+            read2: Info::new(phred, 0, tile),
+            min_overlap_len,
+            max_mismatch_rate,
+            insert: HashMap::new(),
+            total_insert: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // both mates are counted in one shared Info, used by multi-sample table
+    fn merged(phred: u8, min_overlap_len: usize, max_mismatch_rate: f64) -> Self {
+        let stat = Info::new(phred, 0, false);
+        PairInfo {
+            read1: stat.clone(),
+            read2: stat,
+            min_overlap_len,
+            max_mismatch_rate,
+            insert: HashMap::new(),
+            total_insert: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+// insert size of a pair from read overlap, read-through pairs (insert shorter than the read)
+// are tried first, then the 3' end of read1 overlapping the 5' end of reverse complemented read2
+fn insert_size(
+    seq1: &[u8],
+    seq2: &[u8],
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+) -> Option<usize> {
+    insert_by_overlap(seq1, seq2, min_overlap_len, max_mismatch_rate).or_else(|| {
+        find_overlap(
+            seq1,
+            &reverse_complement(seq2),
+            min_overlap_len,
+            max_mismatch_rate,
+        )
+        .map(|(overlap_len, _)| seq1.len() + seq2.len() - overlap_len)
+    })
+}
+
+impl PairedParallelProcessor for PairInfo {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        if let Some(insert_size) = insert_size(
+            rec1.seq(),
+            rec2.seq(),
+            self.min_overlap_len,
+            self.max_mismatch_rate,
+        ) {
+            *self.insert.entry(insert_size).or_insert(0) += 1;
+        }
+        self.read1.process_record(rec1)?;
+        self.read2.process_record(rec2)?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        ParallelProcessor::on_batch_complete(&mut self.read1)?;
+        ParallelProcessor::on_batch_complete(&mut self.read2)?;
+        let mut total_insert = self.total_insert.lock();
+        for (k, v) in self.insert.iter() {
+            *total_insert.entry(*k).or_insert(0) += *v;
+        }
+        self.insert.clear();
         Ok(())
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn stat_fq(
     inp: Option<&String>,
+    inp2: Option<&String>,
     pre_sum: &String,
     pre_cyc: Option<&String>,
+    pre_cyc2: Option<&String>,
+    insert_size: Option<&String>,
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+    read_qual: Option<&String>,
+    tile: Option<&String>,
+    heatmap: Option<&String>,
+    width: usize,
+    height: usize,
+    types: &str,
     phred: u8,
     ncp: usize,
     compression_level: u32,
@@ -303,95 +506,547 @@ pub fn stat_fq(
         error!("{}", FqkitError::InvalidPhredValue);
         std::process::exit(1);
     }
+    if !["svg", "png"].contains(&types) {
+        error!("{}", FqkitError::InvalidFigureType);
+        std::process::exit(1);
+    }
+    if heatmap.is_some() && tile.is_none() {
+        error!("option --heatmap requires option --tile");
+        std::process::exit(1);
+    }
 
-    let fq_reader = fastq::Reader::new(file_reader(inp)?);
     info!("summary result write to file: {}", pre_sum);
     if let Some(file) = pre_cyc {
         info!("cycle result write to file: {}", file);
     } else {
         info!("cycle result write to stdout");
     }
-
-    let mut stat = Info::new(phred, 0);
     let mut fo = file_writer(Some(pre_sum), compression_level, stdout_type)?;
     let mut fc = file_writer(pre_cyc, compression_level, stdout_type)?;
-    fq_reader.process_parallel(stat.clone(), ncp)?;
-
-    stat.calc();
-
-    // output summary result
-    writeln!(&mut fo, "read average length:\t{:.0}", stat.ave_len)?;
-    writeln!(&mut fo, "read min length:\t{}", stat.total_min_len.lock())?;
-    writeln!(&mut fo, "read max length:\t{}", stat.total_max_len.lock())?;
-    writeln!(&mut fo, "total gc content(%):\t{:.2}", stat.rate_gc * 100.0)?;
-    writeln!(&mut fo, "total read count:\t{}", stat.total_num_read.lock())?;
-    writeln!(
-        &mut fo,
-        "total base count:\t{}\n",
-        stat.total_num_base.lock()
-    )?;
-    writeln!(
-        &mut fo,
-        "base A count:\t{}\t({:.2}%)",
-        stat.total_num_a.lock(),
-        stat.rate_a * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "base T count:\t{}\t({:.2}%)",
-        stat.total_num_t.lock(),
-        stat.rate_t * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "base G count:\t{}\t({:.2}%)",
-        stat.total_num_g.lock(),
-        stat.rate_g * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "base C count:\t{}\t({:.2}%)",
-        stat.total_num_c.lock(),
-        stat.rate_c * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "base N count:\t{}\t({:.2}%)\n",
-        stat.total_num_n.lock(),
-        stat.rate_n * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "Number of base calls with quality value of 5 or higher (Q5+) (%)\t{}\t({:.2}%)",
-        stat.total_num_q5.lock(),
-        stat.rate_q5 * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "Number of base calls with quality value of 10 or higher (Q10+) (%)\t{}\t({:.2}%)",
-        stat.total_num_q10.lock(),
-        stat.rate_q10 * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "Number of base calls with quality value of 15 or higher (Q15+) (%)\t{}\t({:.2}%)",
-        stat.total_num_q15.lock(),
-        stat.rate_q15 * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "Number of base calls with quality value of 20 or higher (Q20+) (%)\t{}\t({:.2}%)",
-        stat.total_num_q20.lock(),
-        stat.rate_q20 * 100.0
-    )?;
-    writeln!(
-        &mut fo,
-        "Number of base calls with quality value of 30 or higher (Q30+) (%)\t{}\t({:.2}%)",
-        stat.total_num_q30.lock(),
-        stat.rate_q30 * 100.0
-    )?;
-
-    // output cycle result
+
+    let stats = if let Some(inp2) = inp2 {
+        info!("paired end mode, read2 file: {}", inp2);
+        let fq_reader1 = file_reader(inp).map(fastq::Reader::new)?;
+        let fq_reader2 = file_reader(Some(inp2)).map(fastq::Reader::new)?;
+        let pair = PairInfo::new(phred, tile.is_some(), min_overlap_len, max_mismatch_rate);
+        fq_reader1.process_parallel_paired(fq_reader2, pair.clone(), ncp)?;
+
+        let mut stat1 = pair.read1.clone();
+        let mut stat2 = pair.read2.clone();
+        stat1.calc();
+        stat2.calc();
+        let stats = vec![("read1", stat1), ("read2", stat2)];
+        write_summary(&mut fo, &stats)?;
+
+        // output insert size estimated from overlapped pairs
+        let insert = pair.total_insert.lock();
+        let pairs = *stats[0].1.total_num_read.lock();
+        let overlap = insert.values().sum::<usize>();
+        let insert_bases = insert.iter().map(|(k, v)| k * v).sum::<usize>();
+        writeln!(
+            &mut fo,
+            "\noverlapped pair count:\t{}\t({:.2}%)",
+            overlap,
+            overlap as f64 / pairs as f64 * 100.0
+        )?;
+        writeln!(
+            &mut fo,
+            "insert size average:\t{:.2}",
+            insert_bases as f64 / overlap.max(1) as f64
+        )?;
+        writeln!(
+            &mut fo,
+            "insert size median:\t{:.1}",
+            length_median(&insert)
+        )?;
+        writeln!(&mut fo, "insert size mode:\t{}", length_mode(&insert))?;
+        if let Some(file) = insert_size {
+            info!("insert size result write to file: {}", file);
+            let mut fi = file_writer(Some(file), compression_level, stdout_type)?;
+            let mut sort_insert = insert.iter().collect::<Vec<(&usize, &usize)>>();
+            sort_insert.sort_by_key(|x| x.0);
+            fi.write_all("insert_size\tpairs\n".as_bytes())?;
+            for (k, v) in sort_insert {
+                fi.write_all(format!("{}\t{}\n", k, v).as_bytes())?;
+            }
+            fi.flush()?;
+        }
+
+        write_cycle(&mut fc, &stats[0].1)?;
+        if let Some(file) = pre_cyc2 {
+            info!("read2 cycle result write to file: {}", file);
+            let mut fc2 = file_writer(Some(file), compression_level, stdout_type)?;
+            write_cycle(&mut fc2, &stats[1].1)?;
+        }
+        stats
+    } else {
+        let fq_reader = file_reader(inp).map(fastq::Reader::new)?;
+        let mut stat = Info::new(phred, 0, tile.is_some());
+        fq_reader.process_parallel(stat.clone(), ncp)?;
+        stat.calc();
+
+        let stats = vec![("read", stat)];
+        write_summary(&mut fo, &stats)?;
+        write_cycle(&mut fc, &stats[0].1)?;
+        stats
+    };
+    fo.flush()?;
+
+    // output per-read mean quality distribution
+    if let Some(file) = read_qual {
+        info!("per-read mean quality result write to file: {}", file);
+        let read_qual = stats
+            .iter()
+            .map(|(_, stat)| stat.total_read_qual.lock().clone())
+            .collect::<Vec<HashMap<usize, usize>>>();
+        let max_q = read_qual
+            .iter()
+            .flat_map(|x| x.keys())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        let mut fr = file_writer(Some(file), compression_level, stdout_type)?;
+        let mut header = vec!["mean_quality".to_string()];
+        for (name, _) in stats.iter() {
+            if stats.len() > 1 {
+                header.push(format!("{}_reads\t{}_ratio(%)", name, name));
+            } else {
+                header.push("reads\tratio(%)".to_string());
+            }
+        }
+        fr.write_all(header.join("\t").as_bytes())?;
+        fr.write_all(b"\n")?;
+        for q in 0..=max_q {
+            let mut out = vec![q.to_string()];
+            for each in read_qual.iter() {
+                let total = each.values().sum::<usize>();
+                let num = *each.get(&q).unwrap_or(&0);
+                let rate = if total > 0 {
+                    num as f64 / total as f64 * 100.0
+                } else {
+                    0.0
+                };
+                out.push(format!("{}\t{:.2}", num, rate));
+            }
+            fr.write_all(out.join("\t").as_bytes())?;
+            fr.write_all(b"\n")?;
+        }
+        fr.flush()?;
+    }
+
+    // output per-lane/per-tile mean quality matrix, mates of a pair come from the same cluster
+    if let Some(file) = tile {
+        info!("per-tile mean quality result write to file: {}", file);
+        let mut tile_qual = TileQual::new();
+        for (_, stat) in stats.iter() {
+            for (k, v) in stat.total_tile_qual.lock().iter() {
+                let e = tile_qual.entry(*k).or_insert((0, 0));
+                e.0 += v.0;
+                e.1 += v.1;
+            }
+        }
+        if tile_qual.is_empty() {
+            warn!("no illumina read name (instrument:run:flowcell:lane:tile:x:y) detected");
+        }
+        let (lanes, tiles, matrix) = tile_matrix(&tile_qual);
+        let mut ft = file_writer(Some(file), compression_level, stdout_type)?;
+        let mut header = vec!["tile".to_string()];
+        header.extend(lanes.iter().map(|x| format!("lane{}", x)));
+        ft.write_all(header.join("\t").as_bytes())?;
+        ft.write_all(b"\n")?;
+        for (tile, row) in tiles.iter().zip(matrix.iter()) {
+            let mut out = vec![tile.to_string()];
+            out.extend(row.iter().map(|x| match x {
+                Some(v) => format!("{:.2}", v),
+                None => "NA".to_string(),
+            }));
+            ft.write_all(out.join("\t").as_bytes())?;
+            ft.write_all(b"\n")?;
+        }
+        ft.flush()?;
+
+        if let (Some(prefix), false) = (heatmap, tile_qual.is_empty()) {
+            let name = format!("{}.{}", prefix, types);
+            info!("output per-tile quality heatmap: {}", name);
+            let size = (width as u32, height as u32);
+            if types == "png" {
+                let root = BitMapBackend::new(&name, size).into_drawing_area();
+                plot_tile_heatmap(&root, &lanes, &tiles, &matrix)?;
+            } else {
+                let root = SVGBackend::new(&name, size).into_drawing_area();
+                plot_tile_heatmap(&root, &lanes, &tiles, &matrix)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// one sample in multi-sample mode: (sample name, read1, read2)
+type Sample = (String, String, Option<String>);
+// one row in multi-sample table: (sample name, items)
+type SampleRow = (String, Vec<(&'static str, Value)>);
+
+#[allow(clippy::too_many_arguments)]
+pub fn stat_samples(
+    inputs: &[String],
+    sheet: Option<&String>,
+    pre_sum: &String,
+    multiqc: Option<&String>,
+    min_overlap_len: usize,
+    max_mismatch_rate: f64,
+    phred: u8,
+    ncpu: usize,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), FqkitError> {
+    if ![33u8, 64u8].contains(&phred) {
+        error!("{}", FqkitError::InvalidPhredValue);
+        std::process::exit(1);
+    }
+
+    let mut samples = inputs
+        .iter()
+        .map(|x| (sample_name(x), x.clone(), None))
+        .collect::<Vec<Sample>>();
+    if let Some(sheet) = sheet {
+        info!("reading sample sheet: {}", sheet);
+        samples.extend(sample_sheet(sheet)?);
+    }
+    let mut names = HashSet::new();
+    for (name, _, _) in samples.iter() {
+        if !names.insert(name) {
+            error!("duplicate sample name: {}", name);
+            std::process::exit(1);
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(ncpu).build()?;
+    info!(
+        "total {} samples, processed with {} threads",
+        samples.len(),
+        pool.current_num_threads()
+    );
+
+    // each sample in one thread, samples run in parallel
+    let rows = pool.install(|| {
+        samples
+            .par_iter()
+            .map(|(name, read1, read2)| -> Result<SampleRow, FqkitError> {
+                let fq_reader1 = file_reader(Some(read1)).map(fastq::Reader::new)?;
+                if let Some(read2) = read2 {
+                    let fq_reader2 = file_reader(Some(read2)).map(fastq::Reader::new)?;
+                    let pair = PairInfo::merged(phred, min_overlap_len, max_mismatch_rate);
+                    fq_reader1.process_parallel_paired(fq_reader2, pair.clone(), 1)?;
+                    let mut stat = pair.read1.clone();
+                    stat.calc();
+                    let insert = pair.total_insert.lock();
+                    Ok((name.clone(), table_items(&stat, Some(&insert))))
+                } else {
+                    let mut stat = Info::new(phred, 0, false);
+                    fq_reader1.process_parallel(stat.clone(), 1)?;
+                    stat.calc();
+                    Ok((name.clone(), table_items(&stat, None)))
+                }
+            })
+            .collect::<Result<Vec<SampleRow>, FqkitError>>()
+    })?;
+
+    info!("summary table write to file: {}", pre_sum);
+    let mut fo = file_writer(Some(pre_sum), compression_level, stdout_type)?;
+    if let Some((_, items)) = rows.first() {
+        let header = items.iter().map(|x| x.0).collect::<Vec<&str>>();
+        writeln!(&mut fo, "sample\t{}", header.join("\t"))?;
+    }
+    for (name, items) in rows.iter() {
+        let values = items
+            .iter()
+            .map(|(_, v)| match v {
+                Value::String(x) => x.clone(),
+                Value::Null => "NA".to_string(),
+                _ => v.to_string(),
+            })
+            .collect::<Vec<String>>();
+        writeln!(&mut fo, "{}\t{}", name, values.join("\t"))?;
+    }
+    fo.flush()?;
+
+    // MultiQC custom content, file name should end with _mqc.json
+    if let Some(file) = multiqc {
+        info!("MultiQC custom content write to file: {}", file);
+        let data = rows
+            .iter()
+            .map(|(name, items)| {
+                let items = items
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.clone()))
+                    .collect::<Map<String, Value>>();
+                (name.clone(), Value::Object(items))
+            })
+            .collect::<Map<String, Value>>();
+        let mqc = json!({
+            "id": "fqkit_stats",
+            "section_name": "FqKit stats",
+            "description": "summary for fastq files generated by fqkit stats",
+            "plot_type": "table",
+            "pconfig": {
+                "id": "fqkit_stats_table",
+                "namespace": "fqkit",
+            },
+            "data": data,
+        });
+        let mut fm = file_writer(Some(file), compression_level, stdout_type)?;
+        serde_json::to_writer_pretty(&mut fm, &mqc).map_err(std::io::Error::from)?;
+        fm.write_all(b"\n")?;
+        fm.flush()?;
+    }
+
+    Ok(())
+}
+
+// sample sheet, one sample per line: sample name, read1 file and optional read2 file separated by tab,
+// relative fastq paths are resolved against the directory of the sample sheet
+fn sample_sheet(file: &String) -> Result<Vec<Sample>, FqkitError> {
+    let mut samples = vec![];
+    let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    let path = |x: &str| dir.join(x).to_string_lossy().to_string();
+    let fp = file_reader(Some(file))?;
+    for (num, line) in fp.lines().enumerate() {
+        let (num, line) = (num + 1, line?);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let item = line.split('\t').collect::<Vec<&str>>();
+        match item.len() {
+            2 => samples.push((item[0].to_string(), path(item[1]), None)),
+            3 => samples.push((item[0].to_string(), path(item[1]), Some(path(item[2])))),
+            n => {
+                return Err(FqkitError::InvalidSampleSheet(
+                    file.to_string(),
+                    num,
+                    format!(
+                        "expected sample name, read1 and optional read2 separated by tab, found {} columns",
+                        n
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(samples)
+}
+
+// sample name from fastq file name, eg. path/sample1.fq.gz => sample1
+fn sample_name(file: &str) -> String {
+    let mut name = Path::new(file)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or(file.to_string());
+    for ext in [".gz", ".bz2", ".xz"] {
+        if let Some(x) = name.strip_suffix(ext) {
+            name = x.to_string();
+        }
+    }
+    for ext in [".fq", ".fastq"] {
+        if let Some(x) = name.strip_suffix(ext) {
+            name = x.to_string();
+        }
+    }
+    name
+}
+
+fn round2(x: f64) -> Value {
+    json!((x * 100.0).round() / 100.0)
+}
+
+// items of one sample in multi-sample table, mates of pair end reads are merged
+fn table_items(stat: &Info, insert: Option<&HashMap<usize, usize>>) -> Vec<(&'static str, Value)> {
+    let len_count = stat.total_len_count.lock();
+    let num_read = *stat.total_num_read.lock();
+    let mut items = vec![
+        ("mode", json!(if insert.is_some() { "PE" } else { "SE" })),
+        ("reads", json!(num_read)),
+        ("bases", json!(*stat.total_num_base.lock())),
+        ("avg_len", round2(stat.ave_len)),
+        ("median_len", json!(length_median(&len_count))),
+        ("min_len", json!(stat.total_min_len.lock().unwrap_or(0))),
+        ("max_len", json!(*stat.total_max_len.lock())),
+        ("N50", json!(length_nx(&len_count, 50.0))),
+        ("N90", json!(length_nx(&len_count, 90.0))),
+        ("GC(%)", round2(stat.rate_gc * 100.0)),
+        ("N(%)", round2(stat.rate_n * 100.0)),
+        ("Q20(%)", round2(stat.rate_q20 * 100.0)),
+        ("Q30(%)", round2(stat.rate_q30 * 100.0)),
+    ];
+    if let Some(insert) = insert {
+        let overlap = insert.values().sum::<usize>();
+        // each pair is counted twice in merged Info
+        items.push((
+            "overlap(%)",
+            round2(overlap as f64 * 2.0 / num_read as f64 * 100.0),
+        ));
+        items.push(("insert_median", json!(length_median(insert))));
+    } else {
+        items.push(("overlap(%)", Value::Null));
+        items.push(("insert_median", Value::Null));
+    }
+    items
+}
+
+// summary items of one fastq file: (item name, count, rate)
+pub fn summary_items(stat: &Info) -> Vec<(&'static str, String, Option<f64>)> {
+    let len_count = stat.total_len_count.lock();
+    vec![
+        ("read average length:", format!("{:.2}", stat.ave_len), None),
+        (
+            "read median length:",
+            format!("{:.1}", length_median(&len_count)),
+            None,
+        ),
+        (
+            "read mode length:",
+            length_mode(&len_count).to_string(),
+            None,
+        ),
+        (
+            "read min length:",
+            stat.total_min_len.lock().unwrap_or(0).to_string(),
+            None,
+        ),
+        (
+            "read max length:",
+            stat.total_max_len.lock().to_string(),
+            None,
+        ),
+        (
+            "read N50 length:",
+            length_nx(&len_count, 50.0).to_string(),
+            None,
+        ),
+        (
+            "read N90 length:",
+            length_nx(&len_count, 90.0).to_string(),
+            None,
+        ),
+        (
+            "total gc content(%):",
+            format!("{:.2}", stat.rate_gc * 100.0),
+            None,
+        ),
+        (
+            "total read count:",
+            stat.total_num_read.lock().to_string(),
+            None,
+        ),
+        (
+            "total base count:",
+            stat.total_num_base.lock().to_string(),
+            None,
+        ),
+        ("", String::new(), None),
+        (
+            "base A count:",
+            stat.total_num_a.lock().to_string(),
+            Some(stat.rate_a),
+        ),
+        (
+            "base T count:",
+            stat.total_num_t.lock().to_string(),
+            Some(stat.rate_t),
+        ),
+        (
+            "base G count:",
+            stat.total_num_g.lock().to_string(),
+            Some(stat.rate_g),
+        ),
+        (
+            "base C count:",
+            stat.total_num_c.lock().to_string(),
+            Some(stat.rate_c),
+        ),
+        (
+            "base N count:",
+            stat.total_num_n.lock().to_string(),
+            Some(stat.rate_n),
+        ),
+        ("", String::new(), None),
+        (
+            "Number of base calls with quality value of 5 or higher (Q5+) (%)",
+            stat.total_num_q5.lock().to_string(),
+            Some(stat.rate_q5),
+        ),
+        (
+            "Number of base calls with quality value of 10 or higher (Q10+) (%)",
+            stat.total_num_q10.lock().to_string(),
+            Some(stat.rate_q10),
+        ),
+        (
+            "Number of base calls with quality value of 15 or higher (Q15+) (%)",
+            stat.total_num_q15.lock().to_string(),
+            Some(stat.rate_q15),
+        ),
+        (
+            "Number of base calls with quality value of 20 or higher (Q20+) (%)",
+            stat.total_num_q20.lock().to_string(),
+            Some(stat.rate_q20),
+        ),
+        (
+            "Number of base calls with quality value of 30 or higher (Q30+) (%)",
+            stat.total_num_q30.lock().to_string(),
+            Some(stat.rate_q30),
+        ),
+    ]
+}
+
+// summary result, one column for each fastq file
+fn write_summary<W: Write>(fo: &mut W, stats: &[(&str, Info)]) -> Result<(), FqkitError> {
+    let items = stats
+        .iter()
+        .map(|(_, stat)| summary_items(stat))
+        .collect::<Vec<_>>();
+    if stats.len() > 1 {
+        let names = stats.iter().map(|x| x.0).collect::<Vec<&str>>();
+        writeln!(fo, "item\t{}", names.join("\t"))?;
+    }
+    for (i, (name, _, _)) in items[0].iter().enumerate() {
+        if name.is_empty() {
+            writeln!(fo)?;
+            continue;
+        }
+        let values = items
+            .iter()
+            .map(|x| match x[i].2 {
+                Some(rate) if stats.len() > 1 => format!("{} ({:.2}%)", x[i].1, rate * 100.0),
+                Some(rate) => format!("{}\t({:.2}%)", x[i].1, rate * 100.0),
+                None => x[i].1.clone(),
+            })
+            .collect::<Vec<String>>();
+        writeln!(fo, "{}\t{}", name, values.join("\t"))?;
+    }
+
+    // output read length histogram
+    for (name, stat) in stats.iter() {
+        let num_read = *stat.total_num_read.lock();
+        writeln!(fo, "\n{} length histogram:", name)?;
+        writeln!(fo, "length\tcount\t(%)")?;
+        for (start, end, count) in length_hist(&stat.total_len_count.lock(), 20) {
+            let range = if start == end {
+                format!("{}", start)
+            } else {
+                format!("{}-{}", start, end)
+            };
+            writeln!(
+                fo,
+                "{}\t{}\t({:.2}%)",
+                range,
+                count,
+                count as f64 / num_read as f64 * 100.0
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// cycle result: base count and quality distribution in each read position
+fn write_cycle<W: Write>(fc: &mut W, stat: &Info) -> Result<(), FqkitError> {
     let mut header = vec![
         "cycle".to_string(),
         "A".to_string(),
@@ -430,6 +1085,299 @@ pub fn stat_fq(
         fc.write_all(b"\n")?;
     }
     fc.flush()?;
+    Ok(())
+}
+
+// Nx length: reads of this length or longer contain at least x% of all bases
+fn length_nx(len_count: &HashMap<usize, usize>, x: f64) -> usize {
+    let mut lens = len_count.iter().collect::<Vec<(&usize, &usize)>>();
+    lens.sort_by(|a, b| b.0.cmp(a.0));
+    let total = lens.iter().map(|(l, n)| *l * *n).sum::<usize>() as f64;
+    let mut acc = 0usize;
+    for (len, num) in lens {
+        acc += len * num;
+        if acc as f64 >= total * x / 100.0 {
+            return *len;
+        }
+    }
+    0
+}
+
+fn length_median(len_count: &HashMap<usize, usize>) -> f64 {
+    let mut lens = len_count.iter().collect::<Vec<(&usize, &usize)>>();
+    lens.sort_by_key(|x| x.0);
+    let total = lens.iter().map(|(_, n)| **n).sum::<usize>();
+    if total == 0 {
+        return 0.0;
+    }
+    // 0-based rank of the lower and upper middle read
+    let (lower, upper) = ((total - 1) / 2, total / 2);
+    let (mut lower_len, mut acc) = (None, 0usize);
+    for (len, num) in lens {
+        acc += num;
+        if lower_len.is_none() && acc > lower {
+            lower_len = Some(*len);
+        }
+        if acc > upper {
+            return (lower_len.unwrap_or(*len) + len) as f64 / 2.0;
+        }
+    }
+    0.0
+}
+
+// most frequent read length, the shorter one wins a tie
+fn length_mode(len_count: &HashMap<usize, usize>) -> usize {
+    len_count
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+        .map(|x| *x.0)
+        .unwrap_or(0)
+}
+
+// read length histogram in at most bins intervals: (start, end, read count)
+pub fn length_hist(len_count: &HashMap<usize, usize>, bins: usize) -> Vec<(usize, usize, usize)> {
+    let (Some(min), Some(max)) = (len_count.keys().min(), len_count.keys().max()) else {
+        return vec![];
+    };
+    let width = (max - min + 1).div_ceil(bins);
+    let mut hist = (0..(max - min + 1).div_ceil(width))
+        .map(|i| {
+            let start = min + i * width;
+            (start, (start + width - 1).min(*max), 0usize)
+        })
+        .collect::<Vec<(usize, usize, usize)>>();
+    for (len, num) in len_count.iter() {
+        hist[(len - min) / width].2 += num;
+    }
+    hist
+}
+
+// parse lane and tile from illumina read name, eg. @instrument:run:flowcell:lane:tile:x:y 1:N:0:ATCACG
+fn illumina_lane_tile(id: &[u8]) -> Option<(usize, usize)> {
+    let name = id.split(|x| x.is_ascii_whitespace()).next()?;
+    let fields = name.split(|x| *x == b':').collect::<Vec<&[u8]>>();
+    if fields.len() != 7 {
+        return None;
+    }
+    let lane = std::str::from_utf8(fields[3]).ok()?.parse::<usize>().ok()?;
+    let tile = std::str::from_utf8(fields[4]).ok()?.parse::<usize>().ok()?;
+    Some((lane, tile))
+}
+
+// sorted lanes, sorted tiles and mean quality matrix in row: tile, column: lane
+type TileMatrix = (Vec<usize>, Vec<usize>, Vec<Vec<Option<f64>>>);
+
+fn tile_matrix(tile_qual: &TileQual) -> TileMatrix {
+    let mut lanes = tile_qual.keys().map(|x| x.0).collect::<Vec<usize>>();
+    let mut tiles = tile_qual.keys().map(|x| x.1).collect::<Vec<usize>>();
+    lanes.sort_unstable();
+    lanes.dedup();
+    tiles.sort_unstable();
+    tiles.dedup();
+
+    let matrix = tiles
+        .iter()
+        .map(|tile| {
+            lanes
+                .iter()
+                .map(|lane| {
+                    tile_qual
+                        .get(&(*lane, *tile))
+                        .filter(|(_, n)| *n > 0)
+                        .map(|(q, n)| *q as f64 / *n as f64)
+                })
+                .collect::<Vec<Option<f64>>>()
+        })
+        .collect::<Vec<Vec<Option<f64>>>>();
+    (lanes, tiles, matrix)
+}
+
+// heatmap for per-tile mean quality, low quality in red and high quality in green
+fn plot_tile_heatmap<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    lanes: &[usize],
+    tiles: &[usize],
+    matrix: &[Vec<Option<f64>>],
+) -> Result<(), anyhow::Error>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let values = matrix.iter().flatten().flatten().copied();
+    let min_q = values.clone().fold(f64::MAX, f64::min);
+    let max_q = values.fold(f64::MIN, f64::max);
+    let span = if max_q > min_q { max_q - min_q } else { 1.0 };
+
+    let mut charts = ChartBuilder::on(root)
+        .margin(10)
+        .caption(
+            format!(
+                "Per tile quality plot (red: Q{:.1}, green: Q{:.1})",
+                min_q, max_q
+            ),
+            ("sans-serif", 30).into_font(),
+        )
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(
+            (0..lanes.len() - 1).into_segmented(),
+            (0..tiles.len() - 1).into_segmented(),
+        )?;
+
+    charts
+        .configure_mesh()
+        .disable_mesh()
+        .x_labels(lanes.len())
+        .x_desc("lane")
+        .x_label_formatter(&|x| cell_label(x, lanes))
+        .y_labels(tiles.len().min(40))
+        .y_desc("tile")
+        .y_label_formatter(&|y| cell_label(y, tiles))
+        .draw()?;
+
+    charts.draw_series(matrix.iter().enumerate().flat_map(|(y, row)| {
+        row.iter().enumerate().map(move |(x, q)| {
+            let color = match q {
+                Some(v) => HSLColor((v - min_q) / span * 0.33, 0.8, 0.5).filled(),
+                None => WHITE.filled(),
+            };
+            Rectangle::new(
+                [
+                    (SegmentValue::Exact(x), SegmentValue::Exact(y)),
+                    (cell_end(x, lanes.len()), cell_end(y, tiles.len())),
+                ],
+                color,
+            )
+        })
+    }))?;
+    root.present()?;
 
     Ok(())
 }
+
+// right/top edge of a heatmap cell, the last cell ends at the border of the plot
+fn cell_end(idx: usize, n: usize) -> SegmentValue<usize> {
+    if idx + 1 < n {
+        SegmentValue::Exact(idx + 1)
+    } else {
+        SegmentValue::Last
+    }
+}
+
+fn cell_label(value: &SegmentValue<usize>, names: &[usize]) -> String {
+    match value {
+        SegmentValue::CenterOf(i) => names.get(*i).map(|x| x.to_string()).unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_fastq(lens: &[usize]) -> Vec<u8> {
+        let mut data = vec![];
+        for (i, len) in lens.iter().enumerate() {
+            data.extend_from_slice(format!("@read{}\n", i).as_bytes());
+            data.extend(b"ACGTN".iter().cycle().take(*len));
+            data.extend_from_slice(b"\n+\n");
+            data.extend((0..*len).map(|x| b'#' + (x % 40) as u8));
+            data.push(b'\n');
+        }
+        data
+    }
+
+    #[test]
+    fn merge_batches() {
+        // many batches over several threads, extremes are not in the last batch
+        let lens = (0..20000).map(|x| 50 + x % 97).collect::<Vec<usize>>();
+        let mut lens = lens;
+        lens[10] = 7;
+        lens[20] = 500;
+        let data = fake_fastq(&lens);
+
+        let stat = Info::new(33, 0, false);
+        fastq::Reader::new(data.as_slice())
+            .process_parallel(stat.clone(), 4)
+            .unwrap();
+
+        assert_eq!(*stat.total_num_read.lock(), lens.len());
+        assert_eq!(*stat.total_num_base.lock(), lens.iter().sum::<usize>());
+        assert_eq!(*stat.total_min_len.lock(), Some(7));
+        assert_eq!(*stat.total_max_len.lock(), 500);
+        assert_eq!(*stat.total_max_qva.lock(), 41);
+        assert_eq!(
+            stat.total_len_count.lock().values().sum::<usize>(),
+            lens.len()
+        );
+    }
+
+    #[test]
+    fn pair_insert_size() {
+        let insert = b"TTGACCGATAGCATGGCAGT";
+        let adapter = b"AGATCGGAAGAGC";
+        // insert longer than the read, 30bp reads overlap by 10bp
+        let long = b"ACGGTCAATGCCTAGGTCAATTGACCGATAGCATGGCAGT";
+        assert_eq!(
+            insert_size(&long[..30], &reverse_complement(&long[10..]), 10, 0.1),
+            Some(40)
+        );
+        // read-through pair, 20bp insert followed by adapter in 30bp reads
+        let mut seq1 = insert.to_vec();
+        seq1.extend_from_slice(&adapter[..10]);
+        let mut seq2 = reverse_complement(insert);
+        seq2.extend_from_slice(&adapter[..10]);
+        assert_eq!(insert_size(&seq1, &seq2, 10, 0.1), Some(20));
+        // adapter trimmed reads of the same pair overlap in the usual orientation
+        assert_eq!(insert_size(&seq1[..15], &seq2[..15], 10, 0.1), Some(20));
+        assert_eq!(insert_size(&seq1[..15], &seq2[..15], 11, 0.1), None);
+    }
+
+    #[test]
+    fn empty_input() {
+        let stat = Info::new(33, 0, false);
+        fastq::Reader::new(&b""[..])
+            .process_parallel(stat.clone(), 4)
+            .unwrap();
+        assert_eq!(*stat.total_min_len.lock(), None);
+        assert_eq!(*stat.total_max_len.lock(), 0);
+    }
+
+    #[test]
+    fn length_summary() {
+        let len_count = HashMap::from([(100, 2), (50, 4), (10, 1)]);
+        // total bases 410, 200 bases in reads >= 100bp, 400 in reads >= 50bp
+        assert_eq!(length_nx(&len_count, 50.0), 50);
+        assert_eq!(length_nx(&len_count, 40.0), 100);
+        assert_eq!(length_nx(&len_count, 90.0), 50);
+        assert_eq!(length_median(&len_count), 50.0);
+        assert_eq!(length_median(&HashMap::from([(10, 1), (20, 1)])), 15.0);
+        assert_eq!(length_mode(&len_count), 50);
+        assert_eq!(
+            length_hist(&len_count, 3),
+            vec![(10, 40, 1), (41, 71, 4), (72, 100, 2)]
+        );
+        assert_eq!(
+            length_hist(&HashMap::from([(150, 3)]), 20),
+            vec![(150, 150, 3)]
+        );
+    }
+
+    #[test]
+    fn name_of_sample() {
+        assert_eq!(sample_name("data/s1.fq.gz"), "s1");
+        assert_eq!(sample_name("s2_R1.fastq"), "s2_R1");
+        assert_eq!(sample_name("s3.clean.fq.xz"), "s3.clean");
+    }
+
+    #[test]
+    fn lane_tile() {
+        let id = b"A00123:8:H5KJ3DSXX:2:1101:1018:1000 1:N:0:ATCACG";
+        assert_eq!(illumina_lane_tile(id), Some((2, 1101)));
+    }
+
+    #[test]
+    fn lane_tile_none() {
+        assert_eq!(illumina_lane_tile(b"SRR13753506.1 1/1"), None);
+    }
+}
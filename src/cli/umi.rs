@@ -0,0 +1,333 @@
+use super::misc::{check_synced, write_record};
+use crate::{errors::FqkitError, utils::file_reader, utils::file_writer};
+use log::{error, info};
+use paraseq::{
+    fastq,
+    fastx::Record,
+    parallel::{
+        PairedParallelProcessor, PairedParallelReader, ParallelProcessor, ParallelReader,
+        ProcessError,
+    },
+};
+use parking_lot::Mutex;
+use std::{io::Write, sync::Arc};
+
+type Writer = Arc<Mutex<Box<dyn Write + Send>>>;
+
+// umi pattern from the read start, N: umi base, X: base kept in read, bases after the pattern are kept
+#[derive(Clone, Debug)]
+pub struct UmiPattern {
+    pattern: Vec<u8>,
+}
+
+impl UmiPattern {
+    pub fn new(pattern: &str) -> Self {
+        let pattern = pattern.to_ascii_uppercase().into_bytes();
+        if !pattern.contains(&b'N') || pattern.iter().any(|b| !b"NX".contains(b)) {
+            error!(
+                "invalid umi pattern, only N (umi base) and X (kept base) are allowed, eg. NNNNNNNNXXXX"
+            );
+            std::process::exit(1);
+        }
+        Self { pattern }
+    }
+
+    // umi bases, sequence and quality with umi bases removed, None if the read is shorter than the pattern
+    pub fn extract(&self, seq: &[u8], qual: &[u8]) -> Option<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        if seq.len() < self.pattern.len() {
+            return None;
+        }
+        let mut umi = vec![];
+        let mut kept_seq = Vec::with_capacity(seq.len());
+        let mut kept_qual = Vec::with_capacity(qual.len());
+        for (i, (s, q)) in seq.iter().zip(qual.iter()).enumerate() {
+            if self.pattern.get(i) == Some(&b'N') {
+                umi.push(*s);
+            } else {
+                kept_seq.push(*s);
+                kept_qual.push(*q);
+            }
+        }
+        Some((umi, kept_seq, kept_qual))
+    }
+}
+
+// how umi is added to read name: suffix of read name after sep, or RX:Z: tag in comment
+#[derive(Clone, Debug)]
+pub struct UmiFormat {
+    pub rx_tag: bool,
+    pub sep: String,
+}
+
+impl UmiFormat {
+    pub fn name(&self, id: &[u8], umi: &[u8]) -> Vec<u8> {
+        let mut name = Vec::with_capacity(id.len() + umi.len() + 6);
+        if self.rx_tag {
+            name.extend_from_slice(id);
+            name.extend_from_slice(b" RX:Z:");
+            name.extend_from_slice(umi);
+        } else {
+            let end = id
+                .iter()
+                .position(|x| x.is_ascii_whitespace())
+                .unwrap_or(id.len());
+            name.extend_from_slice(&id[..end]);
+            name.extend_from_slice(self.sep.as_bytes());
+            name.extend_from_slice(umi);
+            name.extend_from_slice(&id[end..]);
+        }
+        name
+    }
+}
+
+#[derive(Clone)]
+struct UmiExtractor {
+    pattern: UmiPattern,
+    // umi in read 1, read 2 or 0 for index read
+    location: usize,
+    format: UmiFormat,
+    buffer1: Vec<u8>,
+    buffer2: Vec<u8>,
+    // reads(pairs) with umi extracted, reads(pairs) dropped for being shorter than pattern
+    count: [usize; 2],
+    total_count: Arc<Mutex<[usize; 2]>>,
+    writer1: Writer,
+    writer2: Option<Writer>,
+}
+
+impl UmiExtractor {
+    // move umi from read (or index read sequence and quality) into read names of both mates
+    fn extract<Rf: Record>(
+        &mut self,
+        rec1: &Rf,
+        rec2: Option<&Rf>,
+        index: Option<(&[u8], &[u8])>,
+    ) -> std::io::Result<()> {
+        let qual1 = rec1.qual().unwrap();
+        let qual2 = rec2.map(|x| x.qual().unwrap()).unwrap_or_default();
+        let seq2 = rec2.map(|x| x.seq()).unwrap_or_default();
+        let (mut seq1, mut kept1) = (rec1.seq().to_vec(), qual1.to_vec());
+        let (mut seq2, mut kept2) = (seq2.to_vec(), qual2.to_vec());
+
+        let umi = match self.location {
+            1 => self.pattern.extract(&seq1, &kept1).map(|(umi, s, q)| {
+                (seq1, kept1) = (s, q);
+                umi
+            }),
+            2 => self.pattern.extract(&seq2, &kept2).map(|(umi, s, q)| {
+                (seq2, kept2) = (s, q);
+                umi
+            }),
+            _ => {
+                let (seq, qual) = index.unwrap_or_default();
+                self.pattern.extract(seq, qual).map(|x| x.0)
+            }
+        };
+        let Some(umi) = umi else {
+            self.count[1] += 1;
+            return Ok(());
+        };
+
+        write_record(
+            &mut self.buffer1,
+            &self.format.name(rec1.id(), &umi),
+            &seq1,
+            &kept1,
+        )?;
+        if let Some(rec2) = rec2 {
+            write_record(
+                &mut self.buffer2,
+                &self.format.name(rec2.id(), &umi),
+                &seq2,
+                &kept2,
+            )?;
+        }
+        self.count[0] += 1;
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> std::io::Result<()> {
+        let mut total_count = self.total_count.lock();
+        for (t, c) in total_count.iter_mut().zip(self.count.iter()) {
+            *t += *c;
+        }
+        drop(total_count);
+
+        let mut writer1 = self.writer1.lock();
+        writer1.write_all(&self.buffer1)?;
+        writer1.flush()?;
+        if let Some(writer2) = &self.writer2 {
+            let mut writer2 = writer2.lock();
+            writer2.write_all(&self.buffer2)?;
+            writer2.flush()?;
+        }
+
+        // reset for next batch
+        self.buffer1.clear();
+        self.buffer2.clear();
+        self.count = [0; 2];
+        Ok(())
+    }
+}
+
+impl ParallelProcessor for UmiExtractor {
+    fn process_record<Rf: Record>(&mut self, record: Rf) -> Result<(), ProcessError> {
+        self.extract(&record, None, None)?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+impl PairedParallelProcessor for UmiExtractor {
+    fn process_record_pair<Rf: Record>(&mut self, rec1: Rf, rec2: Rf) -> Result<(), ProcessError> {
+        self.extract(&rec1, Some(&rec2), None)?;
+        Ok(())
+    }
+
+    fn on_batch_complete(&mut self) -> Result<(), ProcessError> {
+        self.flush_batch()?;
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn extract_umi(
+    read1: &String,
+    read2: Option<&String>,
+    index: Option<&String>,
+    pattern: UmiPattern,
+    location: &str,
+    format: UmiFormat,
+    ncpu: usize,
+    out1: Option<&String>,
+    out2: Option<&String>,
+    compression_level: u32,
+    stdout_type: char,
+) -> Result<(), FqkitError> {
+    if read2.is_some() && (out1.is_none() || out2.is_none()) {
+        error!("both -f/--out1 and -r/--out2 are required for pair end reads");
+        std::process::exit(1);
+    }
+    let location = match location {
+        "index" if index.is_some() => 0,
+        _ if index.is_some() => {
+            error!("-I/--index file is only used with umi location index (-l index)");
+            std::process::exit(1);
+        }
+        "read1" => 1,
+        "read2" if read2.is_some() => 2,
+        "read2" => {
+            error!("umi in read2 requires -2/--read2 file");
+            std::process::exit(1);
+        }
+        _ => {
+            error!("umi in index read requires -I/--index file");
+            std::process::exit(1);
+        }
+    };
+    info!(
+        "umi pattern: {}, umi in: {}",
+        String::from_utf8_lossy(&pattern.pattern),
+        ["index read", "read1", "read2"][location]
+    );
+
+    let fq_reader1 = file_reader(Some(read1)).map(fastq::Reader::new)?;
+    let writer1 = file_writer(out1, compression_level, stdout_type)?;
+    let writer2 = if read2.is_some() {
+        Some(file_writer(out2, compression_level, stdout_type)?)
+    } else {
+        None
+    };
+    let mut extractor = UmiExtractor {
+        pattern,
+        location,
+        format,
+        buffer1: vec![],
+        buffer2: vec![],
+        count: [0; 2],
+        total_count: Arc::new(Mutex::new([0; 2])),
+        writer1: Arc::new(Mutex::new(writer1)),
+        writer2: writer2.map(|w| Arc::new(Mutex::new(w))),
+    };
+
+    let fq_reader2 = read2
+        .map(|x| file_reader(Some(x)).map(fastq::Reader::new))
+        .transpose()?;
+    if let Some(index) = index {
+        // index read in step with read1 (and read2)
+        info!("reading umi from index file: {}", index);
+        let mut readers = vec![("read1", fq_reader1)];
+        if let Some(reader) = fq_reader2 {
+            readers.push(("read2", reader));
+        }
+        readers.push(("index", file_reader(Some(index)).map(fastq::Reader::new)?));
+        let names = readers.iter().map(|x| x.0).collect::<Vec<&str>>();
+        let mut rsets = readers
+            .iter()
+            .map(|_| fastq::RecordSet::default())
+            .collect::<Vec<fastq::RecordSet>>();
+        loop {
+            let mut filled = false;
+            for (rset, (_, reader)) in rsets.iter_mut().zip(readers.iter_mut()) {
+                filled |= rset.fill(reader)?;
+            }
+            if !filled {
+                break;
+            }
+            let records = rsets
+                .iter()
+                .map(|x| x.iter().collect::<Result<Vec<fastq::RefRecord>, _>>())
+                .collect::<Result<Vec<_>, _>>()?;
+            check_synced(&names, &records);
+            let index = records.last().unwrap();
+            for (i, rec1) in records[0].iter().enumerate() {
+                let rec2 = (records.len() == 3).then(|| &records[1][i]);
+                extractor.extract(rec1, rec2, Some((index[i].seq(), index[i].qual())))?;
+            }
+            extractor.flush_batch()?;
+        }
+    } else if let Some(fq_reader2) = fq_reader2 {
+        fq_reader1.process_parallel_paired(fq_reader2, extractor.clone(), ncpu)?;
+    } else {
+        fq_reader1.process_parallel(extractor.clone(), ncpu)?;
+    }
+
+    let count = extractor.total_count.lock();
+    info!("total reads (pairs) with umi extracted: {}", count[0]);
+    info!(
+        "total reads (pairs) dropped, shorter than pattern: {}",
+        count[1]
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_and_format() {
+        let pattern = UmiPattern::new("nnnXnn");
+        let (umi, seq, qual) = pattern.extract(b"ACGTCAGGTT", b"ABCDEFGHIJ").unwrap();
+        assert_eq!(umi, b"ACGCA");
+        assert_eq!(seq, b"TGGTT");
+        assert_eq!(qual, b"DGHIJ");
+        assert!(pattern.extract(b"ACGTC", b"ABCDE").is_none());
+
+        let suffix = UmiFormat {
+            rx_tag: false,
+            sep: "_".to_string(),
+        };
+        assert_eq!(suffix.name(b"r1 1:N:0:1", b"ACG"), b"r1_ACG 1:N:0:1");
+        assert_eq!(suffix.name(b"r1", b"ACG"), b"r1_ACG");
+        let rx = UmiFormat {
+            rx_tag: true,
+            sep: "_".to_string(),
+        };
+        assert_eq!(rx.name(b"r1 1:N:0:1", b"ACG"), b"r1 1:N:0:1 RX:Z:ACG");
+    }
+}
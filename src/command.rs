@@ -1,3 +1,4 @@
+use crate::cli::cutadapter::Aligner;
 use clap::{
     ArgAction, Parser,
     builder::{
@@ -177,32 +178,173 @@ pub enum Subcli {
         #[arg(short = 'o', long = "out", value_name = "FILE")]
         out: Option<String>,
     },
+    /// trim low quality bases by sliding window, leading/trailing and BWA-style 3' trimming
+    #[command(before_help = r"Note:
+    1. trimming steps are run in the order: leading, trailing, sliding window, BWA-style 3' trimming, set 0 to disable a step.
+    2. for pair end reads, a pair is dropped if any mate is shorter than min length, or the surviving mate is written to -u/--unpaired file.")]
+    qtrim {
+        /// input read1 fastq file, or single end fastq file
+        #[arg(short = '1', long = "read1", value_name = "FILE")]
+        read1: String,
+        /// input read2 fastq file, if not specified, trim read1 as single end reads
+        #[arg(short = '2', long = "read2", value_name = "FILE")]
+        read2: Option<String>,
+        /// cut bases off the start of a read, if below this quality
+        #[arg(short = 'L', long = "leading", default_value_t = 0, value_name = "INT")]
+        leading: u8,
+        /// cut bases off the end of a read, if below this quality
+        #[arg(
+            short = 'T',
+            long = "trailing",
+            default_value_t = 0,
+            value_name = "INT"
+        )]
+        trailing: u8,
+        /// sliding window size, cut once the average quality within the window falls below window quality
+        #[arg(short = 'w', long = "window", default_value_t = 0, value_name = "INT")]
+        window: usize,
+        /// required average quality in sliding window
+        #[arg(
+            short = 'W',
+            long = "window-qual",
+            default_value_t = 20,
+            value_name = "INT"
+        )]
+        window_qual: u8,
+        /// trim 3' poly tail of these bases in order, eg. "G" for polyG of NovaSeq/NextSeq, "GA" for polyG then polyA, "X" for a run of any base
+        #[arg(long = "poly", value_name = "STR")]
+        poly: Option<String>,
+        /// min run length of poly tail
+        #[arg(long = "poly-len", default_value_t = 10, value_name = "INT")]
+        poly_len: usize,
+        /// max mismatches allowed in poly tail
+        #[arg(long = "poly-miss", default_value_t = 1, value_name = "INT")]
+        poly_miss: usize,
+        /// BWA-style 3' quality trimming threshold, same as bwa aln -q
+        #[arg(
+            short = 'b',
+            long = "bwa-qual",
+            default_value_t = 0,
+            value_name = "INT"
+        )]
+        bwa_qual: u8,
+        /// reads shorter than length required after trimming will be discarded
+        #[arg(short = 'l', long = "length", default_value_t = 30, value_name = "INT")]
+        length: usize,
+        ///phred score 33 or 64
+        #[arg(short = 'p', long = "phred", default_value_t = 33, value_name = "INT")]
+        phred: u8,
+        /// if set, write the surviving mate of pairs dropped by min length to this file
+        #[arg(short = 'u', long = "unpaired", value_name = "FILE")]
+        unpaired: Option<String>,
+        /// output trimmed read1 fastq file name, or write single end reads to stdout, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'f', long = "out1", value_name = "FILE")]
+        out1: Option<String>,
+        /// output trimmed read2 fastq file name, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'r', long = "out2", value_name = "FILE")]
+        out2: Option<String>,
+    },
     /// cut the adapter sequence on the reads
+    #[command(before_help = r"Note:
+    1. adapters are located by semi-global alignment like cutadapt, 3' adapter may occur anywhere in the read or its prefix hangs off the read end.
+    2. with -l/--left, 5' adapter and all bases before it are removed, otherwise 3' adapter and all bases after it are removed.
+    3. for pair end reads, if reverse complemented read2 overlaps the start of read1, both reads are cut at the insert end:
+       r1:              ACGTTGCAAGCTTCGATCGG|AGATCGGAAGAGC
+       r2 (rev-comp):  GCTCTTCCGATCT|ACGTTGCAAGCTTCGATCGG
+       adapter sequences (-f/--fasta) are aligned to the 3' end of each mate if no overlap is found.
+    4. all adapters are aligned to each read and the best hit is removed (most matched bases, then fewest errors, then fasta order),
+       IUPAC codes (R Y S W K M B D H V N) in adapter or read match any base they stand for.
+    5. adapter sequences in fasta file follow cutadapt notation: ADAPTER (3'), ^ADAPTER (anchored 5', starts at read position 0),
+       ADAPTER$ (anchored 3', ends at read end), linked adapter FRONT...BACK (5' adapter then 3' adapter, anchored parts are required),
+       eg. amplicon primers: ^FWDPRIMER...RCREVPRIMER.")]
     adapter {
-        /// input fastq file, or read from stdin
+        /// input fastq file, or read from stdin, read1 fastq file for pair end reads
         input: Option<String>,
-        /// adapter sequence file in Fasta format
+        /// input read2 fastq file, pair end reads are trimmed at the insert end found by overlap, no adapter sequence is needed
+        #[arg(short = '2', long = "read2", value_name = "FILE")]
+        read2: Option<String>,
+        /// adapter sequence file in Fasta format, optional for pair end reads
         /// {n}Note: fasta sequences must be in single line
         #[arg(short = 'f', long = "fasta", value_name = "FILE")]
-        fa: String,
-        /// addpter on the left side of the read
+        fa: Option<String>,
+        /// built-in adapter library, comma separated for more than one
+        /// {n}supported: truseq, nextera, smallrna, nanopore
+        #[arg(short = 'a', long = "adapter", value_name = "STR")]
+        library: Option<String>,
+        /// guess adapters in use from the first 10000 reads by the built-in adapter library
+        #[arg(short = 'A', long = "auto-detect", help_heading = Some("FLAGS"))]
+        auto_detect: bool,
+        /// addpter on the left side of the read, applies to adapters without anchor or link notation
         #[arg(short, long, help_heading = Some("FLAGS"))]
         left: bool,
-        /// max missmatch allowed
-        #[arg(short, long, default_value_t = 0, value_name = "INT")]
-        miss: usize,
-        /// fastq output file name or write to stdout, files ending in .gz/.bz2/.xz will be compressed automatically
+        /// max error rate (mismatches and indels) allowed in the aligned adapter region
+        #[arg(
+            short = 'e',
+            long = "error-rate",
+            default_value_t = Aligner::default().max_error_rate,
+            value_name = "FLOAT"
+        )]
+        error_rate: f64,
+        /// deprecated, max mismatch count allowed in a full length adapter, use -e/--error-rate instead
+        #[arg(short = 'm', long = "miss", value_name = "INT")]
+        miss: Option<usize>,
+        /// min overlap between read and adapter for a partial adapter at read end
+        #[arg(
+            short = 'O',
+            long = "overlap",
+            default_value_t = Aligner::default().min_overlap,
+            value_name = "INT"
+        )]
+        overlap: usize,
+        /// allow mismatches only, no insertions or deletions in alignment
+        #[arg(short = 'n', long = "no-indels", help_heading = Some("FLAGS"))]
+        no_indels: bool,
+        /// minimum overlap length of pair end reads for insert detection
+        #[arg(
+            short = 'L',
+            long = "pe-length",
+            default_value_t = 30,
+            value_name = "INT"
+        )]
+        pe_length: usize,
+        /// maximum mismatch rate in overlap region of pair end reads
+        #[arg(
+            short = 'M',
+            long = "pe-miss",
+            default_value_t = 0.1,
+            value_name = "FLOAT"
+        )]
+        pe_miss: f64,
+        /// remove adapters up to INT times from each read, the best matching adapter is removed in each round
+        #[arg(short = 't', long = "times", default_value_t = 1, value_name = "INT")]
+        times: usize,
+        /// discard reads (pairs) in which no adapter is found
+        #[arg(short = 'D', long = "discard-untrimmed", help_heading = Some("FLAGS"))]
+        discard_untrimmed: bool,
+        /// write reads in which no adapter is found to this file instead of the output, read1 file for pair end reads
+        #[arg(short = 'u', long = "untrimmed", value_name = "FILE")]
+        untrimmed: Option<String>,
+        /// write untrimmed read2 to this file for pair end reads
+        #[arg(short = 'U', long = "untrimmed2", value_name = "FILE")]
+        untrimmed2: Option<String>,
+        /// output per-adapter hit counts and trimmed bases in json format
+        #[arg(short = 'j', long = "json", value_name = "FILE")]
+        json: Option<String>,
+        /// fastq output file name or write to stdout, read1 output file for pair end reads, files ending in .gz/.bz2/.xz will be compressed automatically
         #[arg(short = 'o', long = "out", value_name = "FILE")]
         out: Option<String>,
+        /// read2 output file name for pair end reads, files ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'r', long = "out2", value_name = "FILE")]
+        out2: Option<String>,
     },
-    /// a simple filter for pair end fastq sqeuence
+    /// a simple filter for single or pair end fastq sqeuence
     filter {
         /// input read1 fastq file
         #[arg(short = '1', long = "read1", value_name = "FILE")]
         read1: String,
-        /// input read2 fastq file
+        /// input read2 fastq file, if not specified, filter read1 as single end reads
         #[arg(short = '2', long = "read2", value_name = "FILE")]
-        read2: String,
+        read2: Option<String>,
         /// if one read number of N base is more then N base limit, then this read pair is discarded.
         #[arg(short = 'n', long = "n-limit", default_value_t = 5, value_name = "INT")]
         nbase: usize,
@@ -213,6 +355,10 @@ pub enum Subcli {
         ///seq = 'AAAATTTTTTTTTTTTTTTTTTTTTGGGGGGGGGGGGGGGGGGGGGGCCCC' and complexity = 3/(51-1) = 6%, the threshold for low complexity filter (0~100). 30 is recommended, which means 30% complexity is required.
         #[arg(short = 'y', long = "complexity", default_value_t = 0, value_parser = value_parser!(u32).range(0..=100), value_name = "INT")]
         complexity: u32,
+        /// complexity method: 'change' for the base change percentage above, 'dust' for 100 - DUST score of trinucleotides (scaled to 0~100),
+        ///'entropy' for shannon entropy of trinucleotides (scaled to 0~100), 70 is recommended for dust and entropy
+        #[arg(short = 'M', long = "complexity-method", default_value_t = String::from("change"), value_parser = ["change", "dust", "entropy"], value_name = "STR")]
+        method: String,
         /// if one read's average quality score < average qual, then this read pair is discarded,
         ///eg. Q20 error 0.01, Q30 error 0.001, averaging the probability of error is 0.0055 => Q value 22.59637
         #[arg(
@@ -222,18 +368,61 @@ pub enum Subcli {
             value_name = "INT"
         )]
         average_qual: u8,
+        /// discard reads with 3' poly tail of these bases in order, eg. "G" for polyG of NovaSeq/NextSeq, "GA" for polyG then polyA, "X" for a run of any base
+        #[arg(long = "poly", value_name = "STR")]
+        poly: Option<String>,
+        /// min run length of poly tail
+        #[arg(long = "poly-len", default_value_t = 10, value_name = "INT")]
+        poly_len: usize,
+        /// max mismatches allowed in poly tail
+        #[arg(long = "poly-miss", default_value_t = 1, value_name = "INT")]
+        poly_miss: usize,
+        /// trim 3' adapters of built-in library before filtering, comma separated for more than one
+        /// {n}supported: truseq, nextera, smallrna, nanopore
+        #[arg(short = 'a', long = "adapter", value_name = "STR")]
+        library: Option<String>,
+        /// guess adapters in use from the first 10000 reads and trim them before filtering
+        #[arg(short = 'A', long = "auto-detect", help_heading = Some("FLAGS"))]
+        auto_detect: bool,
+        /// reads longer than max length will be discarded
+        #[arg(short = 'L', long = "max-length", value_name = "INT")]
+        max_length: Option<usize>,
+        /// if one read's N base fraction (0~1) is more then this value, then this read is discarded
+        #[arg(short = 'N', long = "n-ratio", value_name = "FLOAT")]
+        n_ratio: Option<f64>,
+        /// discard reads with more expected errors (sum of error probabilities) than this value, same as usearch -fastq_maxee
+        #[arg(short = 'e', long = "max-ee", value_name = "FLOAT")]
+        max_ee: Option<f64>,
+        /// quality value for -P/--qual-percent
+        #[arg(long = "min-qual", default_value_t = 20, value_name = "INT")]
+        min_qual: u8,
+        /// discard reads with less than this percentage (0~100) of bases >= min qual, eg. 80 means 80% bases >= Q20 is required
+        #[arg(short = 'P', long = "qual-percent", value_name = "FLOAT")]
+        qual_percent: Option<f64>,
         ///phred score 33 or 64
         #[arg(short = 'p', long = "phred", default_value_t = 33, value_name = "INT")]
         phred: u8,
+        /// pair end policy: 'any' discards a pair if any mate fails, 'either' keeps the passing mate of a pair in -s/--singleton file, not for single end reads
+        #[arg(short = 'm', long = "pair-policy", default_value_t = String::from("any"), value_parser = ["any", "either"], value_name = "STR")]
+        pair_policy: String,
+        /// pair end only, the surviving mate of a failed pair is written to this file with pair policy 'either', required by and only allowed with 'either'
+        #[arg(short = 's', long = "singleton", value_name = "FILE")]
+        singleton: Option<String>,
         /// if set, specify the file to store reads(interleaved) that cannot pass the filters, file ending in .gz/.bz2/.xz will be compressed automatically
         #[arg(short = 'u', long = "failed", value_name = "FILE")]
         failed: Option<String>,
-        /// output pass filtered  forward(read1) fastq file name,  file ending in .gz/.bz2/.xz will be compressed automatically
+        /// if specified, append the failed criteria to the header of reads in failed file, eg. "reason=too_short,low_quality"
+        #[arg(short = 't', long = "tag-reason", help_heading = Some("FLAGS"))]
+        tag: bool,
+        /// if specified, write pass/failed reads number and failures of each criterion per mate to this json file
+        #[arg(short = 'j', long = "json", value_name = "FILE")]
+        json: Option<String>,
+        /// output pass filtered  forward(read1) fastq file name, or write single end reads to stdout, file ending in .gz/.bz2/.xz will be compressed automatically
         #[arg(short = 'f', long = "out1", value_name = "FILE")]
-        out1: String,
+        out1: Option<String>,
         /// output pass filtered resverse(read2) fastq file name,  file ending in .gz/.bz2/.xz will be compressed automatically
         #[arg(short = 'r', long = "out2", value_name = "FILE")]
-        out2: String,
+        out2: Option<String>,
     },
     /// join paired end reads that are overlapping into a single longer read
     #[command(before_help = r"Note:
@@ -317,8 +506,19 @@ pub enum Subcli {
     /// summary for fastq format file
     #[command(visible_alias = "stat", subcommand_help_heading = Some("Statistics"))]
     stats {
-        /// input fastq file, or read from stdin
-        input: Option<String>,
+        /// input fastq file, or read from stdin.
+        /// {n}if more than one file given, summary each file in parallel and write one row per file in summary table
+        input: Vec<String>,
+        /// sample sheet for multi-sample summary table, one sample per line: name, read1 and optional read2 separated by tab,
+        /// relative fastq paths are resolved against the directory of the sample sheet
+        #[arg(short = 'S', long = "sample-sheet", value_name = "FILE")]
+        sheet: Option<String>,
+        /// if specified, write multi-sample summary table as MultiQC custom content to this file, file name should end with _mqc.json
+        #[arg(long = "multiqc", value_name = "FILE")]
+        multiqc: Option<String>,
+        /// if specified, input read2 fastq file and summary pair end reads side by side, the positional input is read1
+        #[arg(short = '2', long = "read2", value_name = "FILE")]
+        read2: Option<String>,
         ///phred score 33 or 64
         #[arg(short = 'p', long = "phred", default_value_t = 33, value_name = "INT")]
         phred: u8,
@@ -328,6 +528,46 @@ pub enum Subcli {
         /// if not specified, cycle result write to stdout
         #[arg(short = 'c', long = "cycle", value_name = "FILE")]
         cyc: Option<String>,
+        /// if specified, write read2 cycle result to this file, just work with option -2/--read2
+        #[arg(short = 'C', long = "cycle2", value_name = "FILE")]
+        cyc2: Option<String>,
+        /// if specified, write insert size distribution of overlapped pair end reads to this file, just work with option -2/--read2
+        #[arg(short = 'i', long = "insert", value_name = "FILE")]
+        insert: Option<String>,
+        /// minimum overlap length in PE reads for insert size estimation
+        #[arg(short = 'l', long = "length", default_value_t = 30, value_name = "INT")]
+        length: usize,
+        /// maximum mismatch rate count in overlap region for insert size estimation
+        #[arg(
+            short = 'm',
+            long = "miss",
+            default_value_t = 0.1,
+            value_name = "FLOAT"
+        )]
+        miss: f64,
+        /// if specified, write per-read mean quality distribution to this file
+        #[arg(short = 'r', long = "read-qual", value_name = "FILE")]
+        read_qual: Option<String>,
+        /// if specified, parse illumina read name (instrument:run:flowcell:lane:tile:x:y) and write per-lane/per-tile mean quality matrix to this file
+        #[arg(short = 't', long = "tile", value_name = "FILE")]
+        tile: Option<String>,
+        /// if specified, output per-tile mean quality heatmap with this prefix name, requires option -t/--tile
+        #[arg(long = "heatmap", value_name = "STR")]
+        heatmap: Option<String>,
+        /// set output heatmap width
+        #[arg(short = 'W', long = "width", default_value_t = 960, value_name = "INT")]
+        width: usize,
+        /// set output heatmap height
+        #[arg(
+            short = 'H',
+            long = "height",
+            default_value_t = 540,
+            value_name = "INT"
+        )]
+        height: usize,
+        /// heatmap figure type 'png' or 'svg'
+        #[arg(short='T', long="types", default_value_t=String::from("png"), value_name = "STR")]
+        types: String,
     },
     /// a simple kmer counter
     kmer {
@@ -522,12 +762,18 @@ pub enum Subcli {
         /// input read1 fastq file
         #[arg(short = '1', long = "read1", value_name = "FILE")]
         read1: String,
-        /// input read2 fastq file <barcode in this file>
+        /// input read2 fastq file <barcode in this file by default>
         #[arg(short = '2', long = "read2", value_name = "FILE")]
         read2: String,
-        /// barcode list file, format eg:
+        /// barcode list file, tab, space or comma separated, barcodes may have different lengths and the longest match wins, format eg:
         /// ATGCAGTG    sample1
         /// TGCAGTAC    sample2
+        /// ACGTCA      sample3
+        /// dual index sample sheet with i7, i5 and sample name:
+        /// ATGCAGTG    TTGCAAGC    sample1
+        /// TGCAGTAC    GCATTGCA    sample2
+        /// blank lines, comment lines starting with '#' and a header line of column names (eg. barcode sample, i7 i5 sample) are skipped,
+        /// Illumina SampleSheet.csv is supported, columns index (index2) and Sample_ID of [Data] section are used
         #[arg(
             short = 'b',
             long = "barcode",
@@ -538,12 +784,56 @@ pub enum Subcli {
         /// barcode position mode, 1:left, 2:right
         #[arg(short = 'm', long = "mode", default_value_t = 2, value_name = "INT")]
         mode: usize,
-        /// barcode reverse complement
+        /// which read the inline barcode is in, 1:read1, 2:read2
+        #[arg(
+            short = 'R',
+            long = "barcode-read",
+            default_value_t = 2,
+            value_name = "INT"
+        )]
+        barcode_read: usize,
+        /// barcode offset from the read end given by mode, bases before the barcode are removed
+        #[arg(short = 'O', long = "offset", default_value_t = 0, value_name = "INT")]
+        offset: usize,
+        /// spacer (linker) length between barcode and insert, spacer bases are removed with barcode
+        #[arg(short = 's', long = "spacer", default_value_t = 0, value_name = "INT")]
+        spacer: usize,
+        /// barcode orientation in sample sheet, as-is: match reads with barcodes as written,
+        /// rc: reverse complement barcodes, only i5 index is reverse complemented in dual index mode
+        #[arg(short = 'd', long = "orient", default_value_t = String::from("as-is"), value_parser = ["as-is", "rc"], value_name = "STR")]
+        orient: String,
+        /// barcode reverse complement, same as --orient rc
         #[arg(short = 'r', long = "rev_comp", help_heading = Some("FLAGS"))]
         trans: bool,
-        /// barcode mismatch base count
+        /// barcode mismatch base count, i7 index mismatch in dual index mode
         #[arg(short = 'e', long = "error", default_value_t = 0, value_name = "INT")]
         mismatch: usize,
+        /// index read1 (i7) fastq file, enable dual index mode, requires -j/--index2
+        #[arg(short = 'i', long = "index1", value_name = "FILE")]
+        index1: Option<String>,
+        /// index read2 (i5) fastq file, enable dual index mode, requires -i/--index1
+        #[arg(short = 'j', long = "index2", value_name = "FILE")]
+        index2: Option<String>,
+        /// read i7 and i5 from read1 header comment, eg. "1:N:0:ACGTACGT+TTGCAAGC", enable dual index mode
+        #[arg(short = 'H', long = "header", help_heading = Some("FLAGS"))]
+        header: bool,
+        /// i5 index mismatch base count in dual index mode
+        #[arg(
+            short = 'E',
+            long = "error-i5",
+            default_value_t = 0,
+            value_name = "INT"
+        )]
+        mismatch_i5: usize,
+        /// output demultiplex report in tsv format: reads per sample, counts by mismatches and top unmatched barcodes
+        #[arg(short = 't', long = "report", value_name = "FILE")]
+        report: Option<String>,
+        /// output demultiplex report in json format
+        #[arg(long = "json", value_name = "FILE")]
+        json: Option<String>,
+        /// number of most frequent unmatched barcodes in report
+        #[arg(short = 'n', long = "top", default_value_t = 10, value_name = "INT")]
+        top: usize,
         /// if specified, output gzip compressed file
         #[arg(short = 'z', long = "gzip", help_heading = Some("FLAGS"))]
         gzip: bool,
@@ -553,10 +843,117 @@ pub enum Subcli {
         /// if specified, output xz compressed file
         #[arg(short = 'x', long = "xz", help_heading = Some("FLAGS"))]
         xz: bool,
-        /// fastq file output dir.
+        /// fastq file output dir, read pairs matching no barcode or more than one barcode equally are written to unmatched_1/2 and ambiguous_1/2 files.
         #[arg(short = 'o', long = "outdir", default_value_t = String::from(".") ,value_name = "DIR")]
         outdir: String,
     },
+    /// extract UMI from reads into read names
+    #[command(before_help = r"Note:
+    1. umi pattern is matched from the read start, N: umi base removed from read, X: base kept in read, bases after the pattern are kept.
+    2. for umi in index read (-l index), umi bases are taken from the index read by pattern and reads are not changed.
+    3. both mates get the same umi in read name, reads (pairs) shorter than the pattern are dropped.")]
+    umi {
+        /// input read1 fastq file, or single end fastq file
+        #[arg(short = '1', long = "read1", value_name = "FILE")]
+        read1: String,
+        /// input read2 fastq file
+        #[arg(short = '2', long = "read2", value_name = "FILE")]
+        read2: Option<String>,
+        /// input index read fastq file carrying the umi
+        #[arg(short = 'I', long = "index", value_name = "FILE")]
+        index: Option<String>,
+        /// umi pattern, eg. NNNNNNNNXXXX for 8 bp umi followed by 4 kept bases
+        #[arg(short = 'p', long = "pattern", value_name = "STR")]
+        pattern: String,
+        /// read carrying the umi
+        #[arg(short = 'l', long = "location", default_value_t = String::from("read1"), value_parser = ["read1", "read2", "index"], value_name = "STR")]
+        location: String,
+        /// umi format in read name, suffix: "@name_UMI comment", rx: "@name comment RX:Z:UMI"
+        #[arg(short = 'F', long = "format", default_value_t = String::from("suffix"), value_parser = ["suffix", "rx"], value_name = "STR")]
+        format: String,
+        /// separator between read name and umi in suffix format
+        #[arg(short = 's', long = "sep", default_value_t = String::from("_"), value_name = "STR")]
+        sep: String,
+        /// output read1 fastq file name, or write single end reads to stdout, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'f', long = "out1", value_name = "FILE")]
+        out1: Option<String>,
+        /// output read2 fastq file name, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'r', long = "out2", value_name = "FILE")]
+        out2: Option<String>,
+    },
+    /// remove PCR duplicates by UMI in read name without alignment
+    #[command(before_help = r"Note:
+    1. umi is taken from RX:Z: tag in read comment, or the read name suffix after the last separator, see subcommand umi.
+    2. reads (pairs) are grouped by sequence prefix of each mate, umis in a group are clustered by UMI-tools directional method:
+       umi a absorbs umi b within mismatches if count(a) >= 2 * count(b) - 1.
+    3. all reads are kept in memory, one read (pair) with the highest quality sum, or a quality-weighted consensus, is written per cluster.
+    4. dedup runs single-threaded, -@/--threads is ignored.")]
+    dedup {
+        /// input read1 fastq file, or single end fastq file
+        #[arg(short = '1', long = "read1", value_name = "FILE")]
+        read1: String,
+        /// input read2 fastq file
+        #[arg(short = '2', long = "read2", value_name = "FILE")]
+        read2: Option<String>,
+        /// sequence prefix length of each mate used for grouping reads
+        #[arg(short = 'k', long = "prefix", default_value_t = 20, value_name = "INT")]
+        prefix: usize,
+        /// umi mismatch base count in directional clustering
+        #[arg(short = 'e', long = "error", default_value_t = 1, value_name = "INT")]
+        mismatch: usize,
+        /// separator between read name and umi
+        #[arg(short = 's', long = "sep", default_value_t = String::from("_"), value_name = "STR")]
+        sep: String,
+        /// output a quality-weighted consensus read (pair) per cluster instead of the best read (pair)
+        #[arg(short = 'c', long = "consensus", help_heading = Some("FLAGS"))]
+        consensus: bool,
+        ///phred score 33 or 64
+        #[arg(short = 'p', long = "phred", default_value_t = 33, value_name = "INT")]
+        phred: u8,
+        /// output read1 fastq file name, or write single end reads to stdout, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'f', long = "out1", value_name = "FILE")]
+        out1: Option<String>,
+        /// output read2 fastq file name, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'r', long = "out2", value_name = "FILE")]
+        out2: Option<String>,
+    },
+    /// correct single-cell barcodes in read1 against a whitelist and tag read2
+    #[command(before_help = r"Note:
+    1. cell barcode is taken from read1 at offset, umi follows the cell barcode, eg. 10x 3' v3: -l 16 -u 12, v2: -l 16 -u 10.
+    2. barcodes not in whitelist are corrected within 1 mismatch, if more than one whitelist barcode is found, the one whose
+       mismatched base has the lowest quality is used, read pairs with tied or no candidates are dropped.
+    3. read2 is written with corrected barcode and umi in read comment: CB:Z:barcode RX:Z:umi.")]
+    cellbc {
+        /// input read1 fastq file with cell barcode and umi
+        #[arg(short = '1', long = "read1", value_name = "FILE")]
+        read1: String,
+        /// input read2 fastq file
+        #[arg(short = '2', long = "read2", value_name = "FILE")]
+        read2: String,
+        /// cell barcode whitelist file, one barcode per line, eg. 3M-february-2018.txt.gz
+        #[arg(short = 'w', long = "whitelist", value_name = "FILE")]
+        whitelist: String,
+        /// cell barcode offset in read1
+        #[arg(short = 'O', long = "offset", default_value_t = 0, value_name = "INT")]
+        offset: usize,
+        /// cell barcode length
+        #[arg(short = 'l', long = "cb-len", default_value_t = 16, value_name = "INT")]
+        cb_len: usize,
+        /// umi length after cell barcode, 0 for no umi
+        #[arg(
+            short = 'u',
+            long = "umi-len",
+            default_value_t = 12,
+            value_name = "INT"
+        )]
+        umi_len: usize,
+        /// output reads per corrected cell barcode in tsv format
+        #[arg(short = 'c', long = "counts", value_name = "FILE")]
+        counts: Option<String>,
+        /// output tagged read2 fastq file name or write to stdout, file ending in .gz/.bz2/.xz will be compressed automatically
+        #[arg(short = 'o', long = "out", value_name = "FILE")]
+        out: Option<String>,
+    },
     /// remove reads by read name.
     #[command(visible_alias = "rm")]
     remove {
@@ -735,6 +1132,34 @@ pub enum Subcli {
         #[arg(short = 'o', long = "out", value_name = "FILE")]
         out: Option<String>,
     },
+    /// summary, quality, GC content and length plots in one html report
+    report {
+        /// input fastq file, or read from stdin
+        input: Option<String>,
+        /// phred score 33 or 64
+        #[arg(short = 'p', long = "phred", default_value_t = 33, value_name = "INT")]
+        phred: u8,
+        /// read complexity method in report: 'change', 'dust' or 'entropy', see filter command for detail
+        #[arg(short = 'M', long = "complexity-method", default_value_t = String::from("dust"), value_parser = ["change", "dust", "entropy"], value_name = "STR")]
+        method: String,
+        /// set report title
+        #[arg(short = 't', long = "title", default_value_t = String::from("FqKit QC report"), value_name = "STR")]
+        title: String,
+        /// set figure width in report
+        #[arg(short = 'W', long = "width", default_value_t = 960, value_name = "INT")]
+        width: usize,
+        /// set figure height in report
+        #[arg(
+            short = 'H',
+            long = "height",
+            default_value_t = 540,
+            value_name = "INT"
+        )]
+        height: usize,
+        /// output html report file name
+        #[arg(short = 'o', long = "out", default_value_t = String::from("report.html"), value_name = "FILE")]
+        out: String,
+    },
     /// view fastq file page by page
     view {
         /// input fastq file
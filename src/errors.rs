@@ -36,6 +36,9 @@ pub enum FqkitError {
     #[error("Empty file: {0}")]
     EmptyFile(String),
 
+    #[error("Invalid sample sheet {0} at line {1}: {2}")]
+    InvalidSampleSheet(String, usize, String),
+
     #[error("Invalid phred value")]
     InvalidPhredValue,
 
@@ -11,10 +11,11 @@ mod utils;
 use command::*;
 mod cli;
 use cli::{
-    barcode::*, concat::*, cutadapter::*, filter::*, flatten::*, fq2fa::*, fq2sam::*, fqscore::*,
-    gcplot::*, grep::*, join::*, kmer::*, length::*, mask::*, merge::*, plot::*, range::*,
-    remove::*, rename::*, reverse::*, search::*, select::*, shuffle::*, size::*, slide::*, sort::*,
-    split::*, split2::*, stats::*, subfq::*, tail::*, top::*, trimfq::*, view::*,
+    barcode::*, cellbc::*, concat::*, cutadapter::*, dedup::*, filter::*, flatten::*, fq2fa::*,
+    fq2sam::*, fqscore::*, gcplot::*, grep::*, join::*, kmer::*, length::*, mask::*, merge::*,
+    plot::*, qtrim::*, range::*, remove::*, rename::*, report::*, reverse::*, search::*, select::*,
+    shuffle::*, size::*, slide::*, sort::*, split::*, split2::*, stats::*, subfq::*, tail::*,
+    top::*, trimfq::*, umi::*, view::*,
 };
 
 fn main() {
@@ -107,19 +108,89 @@ fn run_main() -> Result<(), Error> {
                 arg.stdout_type,
             )?;
         }
+        Subcli::qtrim {
+            read1,
+            read2,
+            leading,
+            trailing,
+            window,
+            window_qual,
+            bwa_qual,
+            poly,
+            poly_len,
+            poly_miss,
+            length,
+            phred,
+            unpaired,
+            out1,
+            out2,
+        } => {
+            let trim = QualTrim {
+                leading,
+                trailing,
+                window,
+                window_qual,
+                bwa_qual,
+                poly: poly.map(|x| PolyTail::new(&x, poly_len, poly_miss)),
+                phred,
+            };
+            qual_trim_fastq(
+                &read1,
+                read2.as_ref(),
+                trim,
+                length,
+                arg.threads,
+                unpaired.as_ref(),
+                out1.as_ref(),
+                out2.as_ref(),
+                arg.compression_level,
+                arg.stdout_type,
+            )?;
+        }
         Subcli::adapter {
             input,
+            read2,
             fa,
+            library,
+            auto_detect,
             left,
+            error_rate,
             miss,
+            overlap,
+            no_indels,
+            pe_length,
+            pe_miss,
+            times,
+            discard_untrimmed,
+            untrimmed,
+            untrimmed2,
+            json,
             out,
+            out2,
         } => {
             cut_adapter(
                 input.as_ref(),
-                &fa,
+                read2.as_ref(),
+                fa.as_ref(),
+                library.as_ref(),
+                auto_detect,
                 left,
+                Aligner {
+                    max_error_rate: error_rate,
+                    min_overlap: overlap,
+                    indels: !no_indels,
+                },
                 miss,
+                times,
+                pe_length,
+                pe_miss,
+                discard_untrimmed,
+                untrimmed.as_ref(),
+                untrimmed2.as_ref(),
+                json.as_ref(),
+                arg.threads,
                 out.as_ref(),
+                out2.as_ref(),
                 arg.compression_level,
                 arg.stdout_type,
             )?;
@@ -253,19 +324,75 @@ fn run_main() -> Result<(), Error> {
         }
         Subcli::stats {
             input,
+            sheet,
+            multiqc,
+            read2,
             phred,
             sum,
             cyc,
+            cyc2,
+            insert,
+            length,
+            miss,
+            read_qual,
+            tile,
+            heatmap,
+            width,
+            height,
+            types,
         } => {
-            stat_fq(
-                input.as_ref(),
-                &sum,
-                cyc.as_ref(),
-                phred,
-                arg.threads,
-                arg.compression_level,
-                arg.stdout_type,
-            )?;
+            if input.len() > 1 || sheet.is_some() {
+                if read2.is_some()
+                    || cyc.is_some()
+                    || cyc2.is_some()
+                    || insert.is_some()
+                    || read_qual.is_some()
+                    || tile.is_some()
+                    || heatmap.is_some()
+                {
+                    error!(
+                        "options -2, -c, -C, -i, -r, -t and --heatmap are not supported for multi-sample summary, give read2 in sample sheet"
+                    );
+                    std::process::exit(1);
+                }
+                stat_samples(
+                    &input,
+                    sheet.as_ref(),
+                    &sum,
+                    multiqc.as_ref(),
+                    length,
+                    miss,
+                    phred,
+                    arg.threads,
+                    arg.compression_level,
+                    arg.stdout_type,
+                )?;
+            } else {
+                if multiqc.is_some() {
+                    error!("option --multiqc only works with multi-sample summary");
+                    std::process::exit(1);
+                }
+                stat_fq(
+                    input.first(),
+                    read2.as_ref(),
+                    &sum,
+                    cyc.as_ref(),
+                    cyc2.as_ref(),
+                    insert.as_ref(),
+                    length,
+                    miss,
+                    read_qual.as_ref(),
+                    tile.as_ref(),
+                    heatmap.as_ref(),
+                    width,
+                    height,
+                    &types,
+                    phred,
+                    arg.threads,
+                    arg.compression_level,
+                    arg.stdout_type,
+                )?;
+            }
         }
         Subcli::shuffle { input, seed, out } => {
             shuffle_fastq(
@@ -328,8 +455,19 @@ fn run_main() -> Result<(), Error> {
             read2,
             bar,
             mode,
+            barcode_read,
+            offset,
+            spacer,
+            orient,
             trans,
             mismatch,
+            index1,
+            index2,
+            header,
+            mismatch_i5,
+            report,
+            json,
+            top,
             gzip,
             bzip2,
             xz,
@@ -339,14 +477,114 @@ fn run_main() -> Result<(), Error> {
                 &read1,
                 &read2,
                 &bar,
-                trans,
-                mode,
+                trans || orient == "rc",
+                InlineLayout {
+                    read: barcode_read,
+                    mode,
+                    offset,
+                    spacer,
+                },
                 mismatch,
+                if index1.is_some() || index2.is_some() || header {
+                    Some(DualIndex {
+                        index1,
+                        index2,
+                        header,
+                        mismatch_i5,
+                    })
+                } else {
+                    None
+                },
+                DemuxReport {
+                    tsv: report,
+                    json,
+                    top,
+                },
                 &outdir,
                 gzip,
                 bzip2,
                 xz,
+                arg.threads,
+                arg.compression_level,
+            )?;
+        }
+        Subcli::umi {
+            read1,
+            read2,
+            index,
+            pattern,
+            location,
+            format,
+            sep,
+            out1,
+            out2,
+        } => {
+            extract_umi(
+                &read1,
+                read2.as_ref(),
+                index.as_ref(),
+                UmiPattern::new(&pattern),
+                &location,
+                UmiFormat {
+                    rx_tag: format == "rx",
+                    sep,
+                },
+                arg.threads,
+                out1.as_ref(),
+                out2.as_ref(),
+                arg.compression_level,
+                arg.stdout_type,
+            )?;
+        }
+        Subcli::dedup {
+            read1,
+            read2,
+            prefix,
+            mismatch,
+            sep,
+            consensus,
+            phred,
+            out1,
+            out2,
+        } => {
+            dedup_umi(
+                &read1,
+                read2.as_ref(),
+                prefix,
+                mismatch,
+                &sep,
+                consensus,
+                phred,
+                out1.as_ref(),
+                out2.as_ref(),
                 arg.compression_level,
+                arg.stdout_type,
+            )?;
+        }
+        Subcli::cellbc {
+            read1,
+            read2,
+            whitelist,
+            offset,
+            cb_len,
+            umi_len,
+            counts,
+            out,
+        } => {
+            correct_cell_barcode(
+                &read1,
+                &read2,
+                &whitelist,
+                CellLayout {
+                    offset,
+                    cb_len,
+                    umi_len,
+                },
+                counts.as_ref(),
+                arg.threads,
+                out.as_ref(),
+                arg.compression_level,
+                arg.stdout_type,
             )?;
         }
         Subcli::filter {
@@ -355,24 +593,60 @@ fn run_main() -> Result<(), Error> {
             nbase,
             length,
             complexity,
+            method,
             average_qual,
+            poly,
+            poly_len,
+            poly_miss,
+            library,
+            auto_detect,
+            max_length,
+            n_ratio,
+            max_ee,
+            min_qual,
+            qual_percent,
             phred,
+            pair_policy,
+            singleton,
             failed,
+            tag,
+            json,
             out1,
             out2,
         } => {
             filter_fastq(
                 &read1,
-                &read2,
+                read2.as_ref(),
                 nbase,
                 length,
                 complexity,
+                Complexity::from_name(&method),
                 average_qual,
+                poly.map(|x| PolyTail::new(&x, poly_len, poly_miss)),
+                ReadLimit {
+                    max_length,
+                    n_ratio,
+                    max_ee,
+                    min_qual,
+                    qual_percent,
+                },
+                select_adapters(
+                    None,
+                    library.as_ref(),
+                    auto_detect,
+                    false,
+                    Some(&read1),
+                    read2.as_ref(),
+                )?,
                 phred,
+                &pair_policy,
                 arg.threads,
                 failed.as_ref(),
-                &out1,
-                &out2,
+                singleton.as_ref(),
+                tag,
+                json.as_ref(),
+                out1.as_ref(),
+                out2.as_ref(),
                 arg.compression_level,
                 arg.stdout_type,
             )?;
@@ -540,6 +814,28 @@ fn run_main() -> Result<(), Error> {
                 arg.stdout_type,
             )?;
         }
+        Subcli::report {
+            input,
+            phred,
+            method,
+            title,
+            width,
+            height,
+            out,
+        } => {
+            report_fq(
+                input.as_ref(),
+                &out,
+                &title,
+                phred,
+                Complexity::from_name(&method),
+                width,
+                height,
+                arg.threads,
+                arg.compression_level,
+                arg.stdout_type,
+            )?;
+        }
         Subcli::view { input, out } => {
             view_fq(
                 input.as_ref(),
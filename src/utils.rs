@@ -208,16 +208,16 @@ mod tests {
 
     #[test]
     fn gz_or_not() {
-        assert_eq!(is_gzipped("example/mini2k.fq.gz").unwrap(), true);
+        assert!(is_gzipped("example/mini2k.fq.gz").unwrap());
     }
 
     #[test]
     fn xz_or_not() {
-        assert_eq!(is_xz("example/mini2k.fq.xz").unwrap(), true);
+        assert!(is_xz("example/mini2k.fq.xz").unwrap());
     }
 
     #[test]
     fn bzip2_or_not() {
-        assert_eq!(is_bzipped("example/mini2k.fq.bz2").unwrap(), true);
+        assert!(is_bzipped("example/mini2k.fq.bz2").unwrap());
     }
 }